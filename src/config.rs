@@ -1,10 +1,23 @@
-use anyhow::{Context, Result};
-use rand::{thread_rng, Rng};
+use anyhow::{anyhow, Context, Result};
+use glob::Pattern;
+use rand::Rng;
+use serde::de::DeserializeOwned;
 use serde_derive::Deserialize;
-use std::{collections::HashMap, fs::File, io::Read};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
 use toml::{value::Table, Value};
 
-use crate::flip_heading;
+use crate::{
+    flip_heading,
+    output::{check_output_dir, check_output_name, check_preset_name},
+    time::check_time,
+    weather::cloud_base_range,
+};
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -14,11 +27,37 @@ pub struct Config {
     pub weather: HashMap<String, Weather>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct MiscOptions {
     #[serde(default)]
     pub remove_required_modules: bool,
+
+    pub output_dir: Option<String>,
+    pub output_name: Option<String>,
+
+    #[serde(default)]
+    pub backup_count: usize,
+
+    pub editors: Option<Vec<String>>,
+
+    /// Archive entries to leave out of every generated miz, by name or glob pattern
+    #[serde(default)]
+    pub remove: Vec<String>,
+
+    pub pause: Option<PauseMode>,
+    pub pause_timeout: Option<u64>,
+    pub pause_prompt: Option<String>,
+}
+
+/// When to wait for a key press before exiting, when running in a terminal
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PauseMode {
+    #[default]
+    Always,
+    OnError,
+    Never,
 }
 
 #[derive(Debug, Deserialize)]
@@ -29,6 +68,17 @@ pub struct Preset {
 
     #[serde(default)]
     pub flip_wind: bool,
+
+    pub script: Option<String>,
+
+    /// Values to set in the `options` file, ie. `[preset.night.options.difficulty]`
+    pub options: Option<Table>,
+    /// Values to set in the `warehouses` file
+    pub warehouses: Option<Table>,
+
+    /// Archive entries to leave out of the miz of this preset, besides the ones of `[misc]`
+    #[serde(default)]
+    pub remove: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -63,6 +113,8 @@ pub struct Weather {
     #[serde(default = "default_weight")]
     pub weight: f64,
 
+    // Already resolved by `preprocess_inheritance`, only kept so the key is accepted
+    #[allow(dead_code)]
     #[serde(default)]
     pub inherit: Vec<String>,
 
@@ -76,54 +128,58 @@ fn default_weight() -> f64 {
 
 // TODO: generate most of this with a macro
 impl Weather {
-    pub fn randomize_wind_flip(&mut self) {
-        self.is_wind_flipped = thread_rng().gen_bool(self.wind_flip_chance);
+    pub fn randomize_wind_flip(&mut self, rng: &mut impl Rng) {
+        self.is_wind_flipped = rng.gen_bool(self.wind_flip_chance);
     }
-    pub fn random_cloud_base(&self) -> Option<i32> {
+    pub fn random_cloud_base(&self, rng: &mut impl Rng) -> Option<i32> {
         match (self.cloud_base_min, self.cloud_base_max) {
             (None, None) => None,
             (None, max) => max,
             (min, None) => min,
-            (Some(min), Some(max)) => Some(thread_rng().gen_range(min..=max)),
+            (Some(min), Some(max)) => Some(rng.gen_range(min..=max)),
         }
     }
-    pub fn random_temp(&self) -> Option<f64> {
+    pub fn random_temp(&self, rng: &mut impl Rng) -> Option<f64> {
         match (self.temp_min, self.temp_max) {
             (None, None) => None,
             (None, max) => max,
             (min, None) => min,
-            (Some(min), Some(max)) => Some(thread_rng().gen_range(min..=max)),
+            (Some(min), Some(max)) => Some(rng.gen_range(min..=max)),
         }
     }
-    pub fn random_qnh(&self) -> Option<f64> {
+    pub fn random_qnh(&self, rng: &mut impl Rng) -> Option<f64> {
         match (self.qnh_min, self.qnh_max) {
             (None, None) => None,
             (None, max) => max,
             (min, None) => min,
-            (Some(min), Some(max)) => Some(thread_rng().gen_range(min..=max)),
+            (Some(min), Some(max)) => Some(rng.gen_range(min..=max)),
         }
     }
-    pub fn random_wind_speed_ground(&self) -> Option<f64> {
+    pub fn random_wind_speed_ground(&self, rng: &mut impl Rng) -> Option<f64> {
         match (self.wind_ground_speed_min, self.wind_ground_speed_max) {
             (None, None) => None,
             (None, max) => max,
             (min, None) => min,
-            (Some(min), Some(max)) => Some(thread_rng().gen_range(min..=max)),
+            (Some(min), Some(max)) => Some(rng.gen_range(min..=max)),
         }
     }
-    pub fn random_wind_heading_ground(&self) -> Option<i32> {
+    pub fn random_wind_heading_ground(&self, rng: &mut impl Rng) -> Option<i32> {
         let mut hdg = match (self.wind_ground_heading_min, self.wind_ground_heading_max) {
             (None, None) => None,
             (None, max) => max,
             (min, None) => min,
-            (Some(min), Some(max)) => Some(thread_rng().gen_range(min..=max)),
+            (Some(min), Some(max)) => Some(rng.gen_range(min..=max)),
         };
         if self.is_wind_flipped {
             hdg = hdg.map(flip_heading);
         }
         hdg
     }
-    pub fn random_wind_speed_2000m(&self, wind_ground_speed: f64) -> Option<f64> {
+    pub fn random_wind_speed_2000m(
+        &self,
+        rng: &mut impl Rng,
+        wind_ground_speed: f64,
+    ) -> Option<f64> {
         match (
             self.wind_2000m_increase_speed_min,
             self.wind_2000m_increase_speed_max,
@@ -131,22 +187,26 @@ impl Weather {
             (None, None) => None,
             (None, max) => max.map(|x| wind_ground_speed + x),
             (min, None) => min.map(|x| wind_ground_speed + x),
-            (Some(min), Some(max)) => Some(wind_ground_speed + thread_rng().gen_range(min..=max)),
+            (Some(min), Some(max)) => Some(wind_ground_speed + rng.gen_range(min..=max)),
         }
     }
-    pub fn random_wind_heading_2000m(&self) -> Option<i32> {
+    pub fn random_wind_heading_2000m(&self, rng: &mut impl Rng) -> Option<i32> {
         let mut hdg = match (self.wind_2000m_heading_min, self.wind_2000m_heading_max) {
             (None, None) => None,
             (None, max) => max,
             (min, None) => min,
-            (Some(min), Some(max)) => Some(thread_rng().gen_range(min..=max)),
+            (Some(min), Some(max)) => Some(rng.gen_range(min..=max)),
         };
         if self.is_wind_flipped {
             hdg = hdg.map(flip_heading);
         }
         hdg
     }
-    pub fn random_wind_speed_8000m(&self, wind_2000m_speed: f64) -> Option<f64> {
+    pub fn random_wind_speed_8000m(
+        &self,
+        rng: &mut impl Rng,
+        wind_2000m_speed: f64,
+    ) -> Option<f64> {
         match (
             self.wind_8000m_increase_speed_min,
             self.wind_8000m_increase_speed_max,
@@ -154,15 +214,15 @@ impl Weather {
             (None, None) => None,
             (None, max) => max.map(|x| wind_2000m_speed + x),
             (min, None) => min.map(|x| wind_2000m_speed + x),
-            (Some(min), Some(max)) => Some(wind_2000m_speed + thread_rng().gen_range(min..=max)),
+            (Some(min), Some(max)) => Some(wind_2000m_speed + rng.gen_range(min..=max)),
         }
     }
-    pub fn random_wind_heading_8000m(&self) -> Option<i32> {
+    pub fn random_wind_heading_8000m(&self, rng: &mut impl Rng) -> Option<i32> {
         let mut hdg = match (self.wind_8000m_heading_min, self.wind_8000m_heading_max) {
             (None, None) => None,
             (None, max) => max,
             (min, None) => min,
-            (Some(min), Some(max)) => Some(thread_rng().gen_range(min..=max)),
+            (Some(min), Some(max)) => Some(rng.gen_range(min..=max)),
         };
         if self.is_wind_flipped {
             hdg = hdg.map(flip_heading);
@@ -171,18 +231,523 @@ impl Weather {
     }
 }
 
-pub fn read_config() -> Result<Config> {
+/// Top-level keys allowed in the configuration embedded in a miz
+const EMBEDDED_KEYS: &[&str] = &["preset", "weather"];
+
+/// A place the configuration can be read from
+#[derive(Debug)]
+pub enum ConfigSource {
+    /// A configuration file on disk
+    File(PathBuf),
+    /// The contents of a `repack.toml` stored inside the miz archive
+    Embedded(Vec<u8>),
+}
+
+impl Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::File(path) => write!(f, "{}", path.display()),
+            ConfigSource::Embedded(_) => write!(f, "repack.toml inside the miz"),
+        }
+    }
+}
+
+/// Returns the configuration sources to read for the given mission, in order of precedence
+///
+/// These are the explicitly provided paths, or `repack.toml` in the current directory (falling
+/// back to `example/repack.toml`) if there are none, followed by the configuration embedded in
+/// the miz, and finally the mission-specific `<mission_name>.repack.toml` next to the miz file,
+/// if it exists.
+pub fn config_sources(
+    explicit_paths: &[PathBuf],
+    miz_path: Option<&Path>,
+    embedded: Option<Vec<u8>>,
+) -> Vec<ConfigSource> {
+    let mut sources: Vec<_> = explicit_paths
+        .iter()
+        .cloned()
+        .map(ConfigSource::File)
+        .collect();
+
+    if sources.is_empty() {
+        let path = PathBuf::from("repack.toml");
+        let fallback = PathBuf::from("example/repack.toml");
+        if !path.is_file() && fallback.is_file() {
+            sources.push(ConfigSource::File(fallback));
+        } else {
+            sources.push(ConfigSource::File(path));
+        }
+    }
+
+    if let Some(embedded) = embedded {
+        sources.push(ConfigSource::Embedded(embedded));
+    }
+
+    if let Some(miz_path) = miz_path {
+        if let Some(stem) = miz_path.file_stem() {
+            let mut file_name = stem.to_owned();
+            file_name.push(".repack.toml");
+            let mission_path = miz_path.with_file_name(file_name);
+            if mission_path.is_file() {
+                sources.push(ConfigSource::File(mission_path));
+            }
+        }
+    }
+
+    sources
+}
+
+/// Reads the configuration from the given sources, with values in later sources overriding earlier ones
+///
+/// Sources that cannot be read, presets that cannot be deserialized and invalid values are all
+/// reported together in the returned error.
+pub fn read_config(sources: &[ConfigSource]) -> Result<Config> {
+    let mut config_data = Value::Table(Table::new());
+    let mut problems = Vec::new();
+
+    for source in sources {
+        let source_data = match source {
+            ConfigSource::File(path) => read_config_file(path, &mut Vec::new(), &mut problems),
+            ConfigSource::Embedded(data) => parse_embedded_config(data),
+        };
+        match source_data {
+            Ok(source_data) => merge_values(&mut config_data, source_data),
+            Err(err) => problems.push(format!("{err:#}")),
+        }
+    }
+
+    let config_data = preprocess_inheritance(config_data, &mut problems);
+    let config = deserialize_config(config_data, &mut problems);
+    if let Some(config) = &config {
+        problems.extend(config.problems());
+    }
+
+    match config {
+        Some(config) if problems.is_empty() => Ok(config),
+        _ => Err(problems_error(&problems)),
+    }
+}
+
+fn problems_error(problems: &[String]) -> anyhow::Error {
+    anyhow!(
+        "Found {} problem(s) in the configuration:\n  - {}",
+        problems.len(),
+        problems.join("\n  - ")
+    )
+}
+
+/// Deserializes the merged configuration entry by entry, so that every invalid preset is
+/// reported instead of only the first one
+///
+/// Invalid presets are left out of the returned configuration.
+fn deserialize_config(config_data: Value, problems: &mut Vec<String>) -> Option<Config> {
+    let Value::Table(mut table) = config_data else {
+        problems.push("The configuration must be a table".to_owned());
+        return None;
+    };
+
+    let misc = match table.remove("misc") {
+        Some(misc) => misc
+            .try_into()
+            .map_err(|err| problems.push(format!("Invalid [misc] table: {err}")))
+            .ok(),
+        None => {
+            problems.push("Missing [misc] table".to_owned());
+            None
+        }
+    };
+    let preset = deserialize_entries(table.remove("preset"), "preset", "Preset", problems);
+    let weather = deserialize_entries(
+        table.remove("weather"),
+        "weather",
+        "Weather preset",
+        problems,
+    );
+    for key in table.keys() {
+        problems.push(format!("Unknown key '{key}' in the configuration"));
+    }
+
+    Some(Config {
+        misc: misc?,
+        preset: preset?,
+        weather: weather?,
+    })
+}
+
+fn deserialize_entries<T: DeserializeOwned>(
+    entries: Option<Value>,
+    key: &str,
+    label: &str,
+    problems: &mut Vec<String>,
+) -> Option<HashMap<String, T>> {
+    let entries = match entries {
+        Some(Value::Table(entries)) => entries,
+        Some(_) => {
+            problems.push(format!("The {key} key must be a table"));
+            return None;
+        }
+        None => {
+            problems.push(format!("Missing [{key}] table"));
+            return None;
+        }
+    };
+
+    let mut entries: Vec<_> = entries.into_iter().collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut values = HashMap::new();
+    for (name, entry) in entries {
+        match entry.try_into() {
+            Ok(value) => {
+                values.insert(name, value);
+            }
+            Err(err) => problems.push(format!("{label} '{name}' is invalid: {err}")),
+        }
+    }
+    Some(values)
+}
+
+impl Config {
+    /// Returns an error listing every problem of the configuration, if it has any
+    pub fn check(&self) -> Result<()> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems_error(&problems))
+        }
+    }
+
+    /// Checks the configuration for invalid values and references, returning every problem found
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if let Some(output_name) = &self.misc.output_name {
+            if let Err(err) = check_output_name(output_name) {
+                problems.push(err.to_string());
+            }
+        }
+        if let Some(output_dir) = &self.misc.output_dir {
+            if let Err(err) = check_output_dir(output_dir) {
+                problems.push(err.to_string());
+            }
+        }
+
+        for pattern in &self.misc.remove {
+            if let Err(err) = Pattern::new(pattern) {
+                problems.push(format!("Invalid remove pattern '{pattern}': {err}"));
+            }
+        }
+
+        let mut presets: Vec<_> = self.preset.iter().collect();
+        presets.sort_by_key(|(name, _)| *name);
+
+        for (preset_name, preset) in presets {
+            if let Err(err) = check_preset_name("Preset", preset_name) {
+                problems.push(err.to_string());
+            }
+            if let Err(err) = check_time(&preset.time) {
+                problems.push(format!(
+                    "Preset '{preset_name}' has an invalid time: {err:#}"
+                ));
+            }
+            for weather_name in preset.weather.iter().flatten() {
+                if !self.weather.contains_key(weather_name) {
+                    problems.push(format!(
+                        "Preset '{preset_name}' references weather preset '{weather_name}', which does not exist"
+                    ));
+                }
+            }
+            let weights: Vec<_> = preset
+                .weather
+                .iter()
+                .flatten()
+                .filter_map(|weather_name| self.weather.get(weather_name))
+                .map(|weather| weather.weight)
+                .collect();
+            if !weights.is_empty() && weights.iter().all(|&weight| weight <= 0.0) {
+                problems.push(format!(
+                    "Preset '{preset_name}' only has weather presets with weight = 0, so none can be chosen"
+                ));
+            }
+            for pattern in &preset.remove {
+                if let Err(err) = Pattern::new(pattern) {
+                    problems.push(format!(
+                        "Preset '{preset_name}' has an invalid remove pattern '{pattern}': {err}"
+                    ));
+                }
+            }
+        }
+
+        let mut weather_presets: Vec<_> = self.weather.iter().collect();
+        weather_presets.sort_by_key(|(name, _)| *name);
+
+        for (preset_name, weather) in weather_presets {
+            if let Err(err) = check_preset_name("Weather preset", preset_name) {
+                problems.push(err.to_string());
+            }
+            weather.check_ranges(preset_name, &mut problems);
+            weather.check_cloud_base(preset_name, &mut problems);
+
+            if !(0.0..=1.0).contains(&weather.wind_flip_chance) {
+                problems.push(format!(
+                    "Weather preset '{preset_name}' has wind_flip_chance = {}, but it must be between 0 and 1",
+                    weather.wind_flip_chance
+                ));
+            }
+            if weather.weight < 0.0 {
+                problems.push(format!(
+                    "Weather preset '{preset_name}' has weight = {}, but it cannot be negative",
+                    weather.weight
+                ));
+            }
+        }
+
+        problems
+    }
+}
+
+impl Weather {
+    fn check_ranges(&self, preset_name: &str, problems: &mut Vec<String>) {
+        fn check<T: PartialOrd + Display>(
+            (key, min, max): (&str, Option<T>, Option<T>),
+            preset_name: &str,
+            problems: &mut Vec<String>,
+        ) {
+            if let (Some(min), Some(max)) = (min, max) {
+                if min > max {
+                    problems.push(format!(
+                        "Weather preset '{preset_name}' has {key}_min = {min}, which is greater than {key}_max = {max}"
+                    ));
+                }
+            }
+        }
+
+        check(
+            ("cloud_base", self.cloud_base_min, self.cloud_base_max),
+            preset_name,
+            problems,
+        );
+        check(
+            ("temp", self.temp_min, self.temp_max),
+            preset_name,
+            problems,
+        );
+        check(("qnh", self.qnh_min, self.qnh_max), preset_name, problems);
+        check(
+            (
+                "wind_ground_speed",
+                self.wind_ground_speed_min,
+                self.wind_ground_speed_max,
+            ),
+            preset_name,
+            problems,
+        );
+        check(
+            (
+                "wind_ground_heading",
+                self.wind_ground_heading_min,
+                self.wind_ground_heading_max,
+            ),
+            preset_name,
+            problems,
+        );
+        check(
+            (
+                "wind_2000m_increase_speed",
+                self.wind_2000m_increase_speed_min,
+                self.wind_2000m_increase_speed_max,
+            ),
+            preset_name,
+            problems,
+        );
+        check(
+            (
+                "wind_2000m_heading",
+                self.wind_2000m_heading_min,
+                self.wind_2000m_heading_max,
+            ),
+            preset_name,
+            problems,
+        );
+        check(
+            (
+                "wind_8000m_increase_speed",
+                self.wind_8000m_increase_speed_min,
+                self.wind_8000m_increase_speed_max,
+            ),
+            preset_name,
+            problems,
+        );
+        check(
+            (
+                "wind_8000m_heading",
+                self.wind_8000m_heading_min,
+                self.wind_8000m_heading_max,
+            ),
+            preset_name,
+            problems,
+        );
+    }
+
+    fn check_cloud_base(&self, preset_name: &str, problems: &mut Vec<String>) {
+        let cloud_preset = match &self.cloud_preset {
+            Some(cloud_preset) => cloud_preset,
+            None => return,
+        };
+
+        let (min, max) = match cloud_base_range(cloud_preset) {
+            Some(range) => range,
+            None => {
+                problems.push(format!(
+                    "Weather preset '{preset_name}' uses unknown cloud preset '{cloud_preset}'"
+                ));
+                return;
+            }
+        };
+
+        for (key, value) in [
+            ("cloud_base_min", self.cloud_base_min),
+            ("cloud_base_max", self.cloud_base_max),
+        ] {
+            if let Some(value) = value {
+                if value < min || value > max {
+                    problems.push(format!(
+                        "Weather preset '{preset_name}' has {key} = {value}, but cloud preset '{cloud_preset}' only allows bases between {min} and {max}"
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Reads a single configuration file, along with any files listed in its `include` key
+///
+/// Included files that cannot be read are added to `problems`, and the other files are still
+/// read.
+fn read_config_file(
+    path: &Path,
+    include_stack: &mut Vec<PathBuf>,
+    problems: &mut Vec<String>,
+) -> Result<Value> {
     let mut data = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut data))
+        .with_context(|| format!("Cannot open config file {}", path.display()))?;
+
+    let canonical_path = path.canonicalize()?;
+    if include_stack.contains(&canonical_path) {
+        return Err(anyhow!("Config file {} includes itself", path.display()));
+    }
 
-    File::open("repack.toml")
-        .or_else(|original_error| File::open("example/repack.toml").map_err(|_| original_error))?
-        .read_to_end(&mut data)?;
+    include_stack.push(canonical_path);
+    let config_data = parse_config(
+        &data,
+        &path.display().to_string(),
+        path.parent().unwrap_or_else(|| Path::new("")),
+        include_stack,
+        problems,
+    )?;
+    include_stack.pop();
 
-    let config_data = preprocess_inheritance(toml::from_slice(&data)?)?;
-    Ok(config_data.try_into()?)
+    Ok(config_data)
+}
+
+/// Parses configuration data, resolving the files listed in its `include` key
+///
+/// Included files are resolved relative to `base_dir`, and the values of the including
+/// file override the ones it includes.
+fn parse_config(
+    data: &[u8],
+    name: &str,
+    base_dir: &Path,
+    include_stack: &mut Vec<PathBuf>,
+    problems: &mut Vec<String>,
+) -> Result<Value> {
+    let mut file_data: Value =
+        toml::from_slice(data).with_context(|| format!("Cannot parse config file {name}"))?;
+
+    let includes = match file_data
+        .as_table_mut()
+        .and_then(|table| table.remove("include"))
+    {
+        Some(Value::Array(includes)) => includes,
+        Some(_) => return Err(anyhow!("The include key in {name} must be a list of paths")),
+        None => Vec::new(),
+    };
+
+    let mut config_data = Value::Table(Table::new());
+    for include in includes {
+        let include = include
+            .as_str()
+            .with_context(|| format!("The include key in {name} must be a list of paths"))?;
+        match read_config_file(&base_dir.join(include), include_stack, problems) {
+            Ok(include_data) => merge_values(&mut config_data, include_data),
+            Err(err) => problems.push(format!(
+                "{:#}",
+                err.context(format!("Included from {name}"))
+            )),
+        }
+    }
+    merge_values(&mut config_data, file_data);
+
+    Ok(config_data)
+}
+
+/// Parses the configuration embedded in a miz, which may only contain presets
+///
+/// Downloaded missions must not choose where files are written on the server or which
+/// files are read, so `[misc]`, `include` and preset names that are not plain file names are
+/// rejected.
+fn parse_embedded_config(data: &[u8]) -> Result<Value> {
+    let config_data: Value =
+        toml::from_slice(data).context("Cannot parse the repack.toml inside the miz")?;
+    if let Some(table) = config_data.as_table() {
+        for (key, kind) in [("preset", "Preset"), ("weather", "Weather preset")] {
+            let names = table
+                .get(key)
+                .and_then(Value::as_table)
+                .into_iter()
+                .flatten();
+            for (name, _) in names {
+                check_preset_name(kind, name)
+                    .context("The repack.toml inside the miz has an invalid preset name")?;
+            }
+        }
+
+        let mut keys: Vec<_> = table
+            .keys()
+            .filter(|key| !EMBEDDED_KEYS.contains(&key.as_str()))
+            .map(String::as_str)
+            .collect();
+        if !keys.is_empty() {
+            keys.sort();
+            return Err(anyhow!(
+                "The repack.toml inside the miz can only contain [preset] and [weather], found: {}",
+                keys.join(", ")
+            ));
+        }
+    }
+    Ok(config_data)
 }
 
-fn preprocess_inheritance(mut config_data: Value) -> Result<Value> {
+/// Recursively merges the source value into the destination, with the source taking precedence
+fn merge_values(destination: &mut Value, source: Value) {
+    match (destination, source) {
+        (Value::Table(destination), Value::Table(source)) => {
+            for (key, value) in source {
+                match destination.get_mut(&key) {
+                    Some(existing) => merge_values(existing, value),
+                    None => {
+                        destination.insert(key, value);
+                    }
+                }
+            }
+        }
+        (destination, source) => *destination = source,
+    }
+}
+
+fn preprocess_inheritance(mut config_data: Value, problems: &mut Vec<String>) -> Value {
     if let Some(presets) = config_data
         .get_mut("weather")
         .and_then(|weather_data| weather_data.as_table_mut())
@@ -193,24 +758,35 @@ fn preprocess_inheritance(mut config_data: Value) -> Result<Value> {
         for (preset_name, preset) in presets.iter() {
             if let Some(inherits) = preset.get("inherit").and_then(|inherit| inherit.as_array()) {
                 for inherit in inherits {
-                    inheritance_pairs
-                        .push((preset_name.clone(), inherit.as_str().unwrap().to_owned()));
+                    match inherit.as_str() {
+                        Some(inherit) => {
+                            inheritance_pairs.push((preset_name.clone(), inherit.to_owned()))
+                        }
+                        None => problems.push(format!(
+                            "Preset '{preset_name}' has an inherit value that is not a preset name: {inherit}"
+                        )),
+                    }
                 }
             }
         }
 
         // Replace them in the presets map
         for (preset_name, inherited_name) in inheritance_pairs {
-            let preset = presets
-                .get(&preset_name)
-                .unwrap()
-                .as_table()
-                .unwrap()
-                .clone();
+            let Some(preset) = presets.get(&preset_name).and_then(Value::as_table).cloned() else {
+                continue;
+            };
 
-            let inherited_values = presets.get(&inherited_name)
-                .with_context(|| format!("Preset '{preset_name}' tries to inherit values from '{inherited_name}', but the referenced preset does not exist"))?
-                .as_table().with_context(|| format!("Preset '{preset_name}' tries to inherit values from '{inherited_name}', but it is not a table"))?;
+            let inherited_values = match presets.get(&inherited_name).map(Value::as_table) {
+                Some(Some(inherited_values)) => inherited_values,
+                Some(None) => {
+                    problems.push(format!("Preset '{preset_name}' tries to inherit values from '{inherited_name}', but it is not a table"));
+                    continue;
+                }
+                None => {
+                    problems.push(format!("Preset '{preset_name}' tries to inherit values from '{inherited_name}', but the referenced preset does not exist"));
+                    continue;
+                }
+            };
 
             presets.insert(
                 preset_name,
@@ -219,7 +795,7 @@ fn preprocess_inheritance(mut config_data: Value) -> Result<Value> {
         }
     }
 
-    Ok(config_data)
+    config_data
 }
 
 fn merge_tables(mut destination: Table, source: &Table) -> Table {
@@ -228,3 +804,293 @@ fn merge_tables(mut destination: Table, source: &Table) -> Table {
     }
     destination
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn valid_config_has_no_problems() {
+        let config = config(
+            r#"
+            [misc]
+            [preset.morning]
+            time = "06:00"
+            weather = ["clear"]
+            # Relative to the folder of the miz, which is not known here
+            script = "edits.lua"
+            [weather.clear]
+            cloud_preset = "Preset1"
+            temp_min = 10.0
+            temp_max = 20.0
+            "#,
+        );
+        assert_eq!(config.problems(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let config = config(
+            r#"
+            [misc]
+            [preset.morning]
+            time = "noon"
+            weather = ["missing"]
+            [weather.bad]
+            temp_min = 20.0
+            temp_max = 10.0
+            wind_flip_chance = 2.0
+            weight = -1.0
+            "#,
+        );
+        let problems = config.problems();
+        assert_eq!(problems.len(), 5, "{problems:#?}");
+        assert!(problems[0].starts_with("Preset 'morning' has an invalid time"));
+        assert!(problems[1].contains("references weather preset 'missing'"));
+        assert!(problems[2].contains("temp_min = 20, which is greater than temp_max = 10"));
+        assert!(problems[3].contains("wind_flip_chance = 2"));
+        assert!(problems[4].contains("weight = -1"));
+    }
+
+    #[test]
+    fn rejects_times_outside_of_the_day() {
+        let config = config(
+            r#"
+            [misc]
+            [preset.negative]
+            time = "-1:00"
+            [preset.late]
+            time = "24:00"
+            [preset.minutes]
+            time = "06:75"
+            [preset.valid]
+            time = "23:59:59"
+            [weather]
+            "#,
+        );
+        let problems = config.problems();
+        assert_eq!(problems.len(), 3, "{problems:#?}");
+        assert!(problems[0].starts_with("Preset 'late' has an invalid time"));
+        assert!(problems[1].starts_with("Preset 'minutes' has an invalid time"));
+        assert!(problems[2].starts_with("Preset 'negative' has an invalid time"));
+    }
+
+    #[test]
+    fn rejects_presets_without_a_weather_to_choose() {
+        let config = config(
+            r#"
+            [misc]
+            [preset.calm]
+            time = "06:00"
+            weather = ["never", "disabled"]
+            [preset.mixed]
+            time = "06:00"
+            weather = ["never", "clear"]
+            [weather.never]
+            weight = 0.0
+            [weather.disabled]
+            weight = 0.0
+            [weather.clear]
+            "#,
+        );
+        let problems = config.problems();
+        assert_eq!(problems.len(), 1, "{problems:#?}");
+        assert!(problems[0].starts_with("Preset 'calm' only has weather presets with weight = 0"));
+    }
+
+    #[test]
+    fn reports_read_errors_together() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("broken.toml"), "[weather.clear\n").unwrap();
+        let main_path = dir.path().join("repack.toml");
+        std::fs::write(
+            &main_path,
+            r#"
+            include = ["missing.toml", "broken.toml"]
+            [misc]
+            [preset.morning]
+            time = "06:00"
+            unknown_key = 1
+            [preset.evening]
+            time = 18
+            [preset.night]
+            time = "25:00"
+            [weather.rain]
+            inherit = ["missing"]
+            "#,
+        )
+        .unwrap();
+        let other_path = dir.path().join("other.toml");
+        std::fs::write(&other_path, "[misc\n").unwrap();
+
+        let err = read_config(&[
+            ConfigSource::File(main_path),
+            ConfigSource::File(other_path),
+        ])
+        .unwrap_err();
+        let message = err.to_string();
+        assert!(message.starts_with("Found 7 problem(s)"), "{message}");
+        for problem in [
+            "Cannot open config file",
+            "Cannot parse config file",
+            "broken.toml",
+            "other.toml",
+            "'rain' tries to inherit values from 'missing'",
+            "Preset 'evening' is invalid",
+            "Preset 'morning' is invalid",
+            "Preset 'night' has an invalid time",
+        ] {
+            assert!(message.contains(problem), "{problem} in {message}");
+        }
+    }
+
+    #[test]
+    fn checks_cloud_base_against_cloud_preset() {
+        let config = config(
+            r#"
+            [misc]
+            [preset]
+            [weather.low]
+            cloud_preset = "Preset1"
+            cloud_base_min = 0
+            [weather.unknown]
+            cloud_preset = "NoSuchPreset"
+            "#,
+        );
+        let problems = config.problems();
+        assert_eq!(problems.len(), 2, "{problems:#?}");
+        assert!(problems[0].contains("cloud_base_min = 0, but cloud preset 'Preset1'"));
+        assert!(problems[1].contains("unknown cloud preset 'NoSuchPreset'"));
+    }
+
+    #[test]
+    fn merge_values_overrides_key_by_key() {
+        let mut destination: Value = toml::from_str(
+            r#"
+            [misc]
+            backup_count = 2
+            output_dir = "out"
+            [weather.clear]
+            temp_min = 10.0
+            temp_max = 20.0
+            "#,
+        )
+        .unwrap();
+        let source: Value = toml::from_str(
+            r#"
+            [misc]
+            backup_count = 5
+            [weather.clear]
+            temp_max = 25.0
+            [weather.rain]
+            weight = 2.0
+            "#,
+        )
+        .unwrap();
+
+        merge_values(&mut destination, source);
+
+        let expected: Value = toml::from_str(
+            r#"
+            [misc]
+            backup_count = 5
+            output_dir = "out"
+            [weather.clear]
+            temp_min = 10.0
+            temp_max = 25.0
+            [weather.rain]
+            weight = 2.0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(destination, expected);
+    }
+
+    #[test]
+    fn merge_values_replaces_arrays() {
+        let mut destination: Value = toml::from_str(r#"weather = ["a", "b"]"#).unwrap();
+        merge_values(
+            &mut destination,
+            toml::from_str(r#"weather = ["c"]"#).unwrap(),
+        );
+        assert_eq!(destination, toml::from_str(r#"weather = ["c"]"#).unwrap());
+    }
+
+    #[test]
+    fn including_file_overrides_included_values() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("weather.toml"),
+            "[weather.clear]\ntemp_min = 10.0\ntemp_max = 20.0\n",
+        )
+        .unwrap();
+        let main_path = dir.path().join("repack.toml");
+        std::fs::write(
+            &main_path,
+            "include = [\"weather.toml\"]\n[misc]\n[preset]\n[weather.clear]\ntemp_max = 30.0\n",
+        )
+        .unwrap();
+
+        let config = read_config(&[ConfigSource::File(main_path)]).unwrap();
+        let clear = &config.weather["clear"];
+        assert_eq!(clear.temp_min, Some(10.0));
+        assert_eq!(clear.temp_max, Some(30.0));
+    }
+
+    #[test]
+    fn rejects_include_cycles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("repack.toml");
+        std::fs::write(&path, "include = [\"repack.toml\"]\n").unwrap();
+
+        let err = read_config(&[ConfigSource::File(path)]).unwrap_err();
+        assert!(format!("{err:#}").contains("includes itself"), "{err:#}");
+    }
+
+    #[test]
+    fn embedded_config_can_only_set_presets() {
+        let data = b"[preset.night]\ntime = \"22:00\"\n[weather.clear]\nweight = 2.0\n";
+        assert!(parse_embedded_config(data).is_ok());
+
+        let data = b"include = [\"/etc/other.toml\"]\n[misc]\noutput_dir = \"/tmp\"\n";
+        let err = parse_embedded_config(data).unwrap_err();
+        assert!(err.to_string().ends_with("found: include, misc"), "{err}");
+    }
+
+    #[test]
+    fn embedded_config_cannot_choose_where_files_are_written() {
+        for data in [
+            &b"[preset.\"../../x\"]\ntime = \"06:00\"\n"[..],
+            b"[preset.\"C:/Windows/x\"]\ntime = \"06:00\"\n",
+            b"[preset.\"..\\\\x\"]\ntime = \"06:00\"\n",
+            b"[weather.\"../../weather\"]\nweight = 2.0\n",
+            b"[weather.\".git\"]\nweight = 2.0\n",
+        ] {
+            let err = parse_embedded_config(data).unwrap_err();
+            assert!(
+                format!("{err:#}").contains("cannot be used in file names"),
+                "{err:#}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_preset_names_that_are_not_file_names() {
+        let config = config(
+            r#"
+            [misc]
+            [preset."../x"]
+            time = "06:00"
+            [weather."a/b"]
+            "#,
+        );
+        let problems = config.problems();
+        assert_eq!(problems.len(), 2, "{problems:#?}");
+        assert!(problems[0].starts_with("Preset '../x' cannot be used in file names"));
+        assert!(problems[1].starts_with("Weather preset 'a/b' cannot be used in file names"));
+    }
+}
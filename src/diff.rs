@@ -0,0 +1,135 @@
+use crate::{
+    inspect::{open_miz, read_mission},
+    lua::{parse_lua_assignment, LuaKey, LuaValue},
+};
+use anyhow::{Context, Result};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    path::Path,
+};
+
+/// A difference between two Lua values, identified by its dot-separated key path
+#[derive(Debug)]
+pub enum LuaChange {
+    Added(String, LuaValue),
+    Removed(String, LuaValue),
+    Changed(String, LuaValue, LuaValue),
+}
+
+impl Display for LuaChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LuaChange::Added(path, value) => write!(f, "   + {path} = {value}"),
+            LuaChange::Removed(path, value) => write!(f, "   - {path} = {value}"),
+            LuaChange::Changed(path, old, new) => write!(f, "   ~ {path}: {old} -> {new}"),
+        }
+    }
+}
+
+/// Compares two Lua values key by key, returning every added, removed and changed value
+pub fn diff_lua(old: &LuaValue, new: &LuaValue) -> Vec<LuaChange> {
+    let mut changes = Vec::new();
+    diff_lua_at("", old, new, &mut changes);
+    changes
+}
+
+fn diff_lua_at(path: &str, old: &LuaValue, new: &LuaValue, changes: &mut Vec<LuaChange>) {
+    let join = |key: &LuaKey| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{path}.{key}")
+        }
+    };
+
+    match (old, new) {
+        (LuaValue::Table(old_table), LuaValue::Table(new_table)) => {
+            for (key, old_value) in old_table {
+                match new_table.iter().find(|(new_key, _)| new_key == key) {
+                    Some((_, new_value)) => diff_lua_at(&join(key), old_value, new_value, changes),
+                    None => changes.push(LuaChange::Removed(join(key), old_value.clone())),
+                }
+            }
+            for (key, new_value) in new_table {
+                if !old_table.iter().any(|(old_key, _)| old_key == key) {
+                    changes.push(LuaChange::Added(join(key), new_value.clone()));
+                }
+            }
+        }
+        (old, new) if old != new => changes.push(LuaChange::Changed(
+            path.to_owned(),
+            old.clone(),
+            new.clone(),
+        )),
+        _ => {}
+    }
+}
+
+/// Returns the CRC32 and size of every entry in a miz archive
+fn read_entries(miz_path: &Path) -> Result<BTreeMap<String, (u32, u64)>> {
+    let mut archive = open_miz(miz_path)?;
+    let mut entries = BTreeMap::new();
+    for idx in 0..archive.len() {
+        let file = archive.by_index_raw(idx)?;
+        entries.insert(file.name().to_owned(), (file.crc32(), file.size()));
+    }
+    Ok(entries)
+}
+
+/// The archive entries and mission values that differ between two miz files
+#[derive(Debug)]
+pub struct MizDiff {
+    pub entries: Vec<EntryChange>,
+    pub mission: Vec<LuaChange>,
+}
+
+/// A difference between the entries of two miz archives, by entry name
+#[derive(Debug)]
+pub enum EntryChange {
+    Added(String),
+    Removed(String),
+    Changed(String),
+}
+
+impl Display for EntryChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntryChange::Added(name) => write!(f, "   + {name}"),
+            EntryChange::Removed(name) => write!(f, "   - {name}"),
+            EntryChange::Changed(name) => write!(f, "   ~ {name}"),
+        }
+    }
+}
+
+/// Compares the archive entries and mission values of two miz files
+pub fn diff_miz(old_path: &Path, new_path: &Path) -> Result<MizDiff> {
+    let old_entries = read_entries(old_path)?;
+    let new_entries = read_entries(new_path)?;
+
+    let mut entries = Vec::new();
+    for (name, old_entry) in &old_entries {
+        match new_entries.get(name) {
+            Some(new_entry) if new_entry != old_entry => {
+                entries.push(EntryChange::Changed(name.clone()))
+            }
+            Some(_) => {}
+            None => entries.push(EntryChange::Removed(name.clone())),
+        }
+    }
+    for name in new_entries.keys() {
+        if !old_entries.contains_key(name) {
+            entries.push(EntryChange::Added(name.clone()));
+        }
+    }
+
+    let (_, old_mission) = parse_lua_assignment(&read_mission(old_path)?)
+        .with_context(|| format!("Cannot read mission file of {}", old_path.display()))?;
+    let (_, new_mission) = parse_lua_assignment(&read_mission(new_path)?)
+        .with_context(|| format!("Cannot read mission file of {}", new_path.display()))?;
+
+    Ok(MizDiff {
+        entries,
+        mission: diff_lua(&old_mission, &new_mission),
+    })
+}
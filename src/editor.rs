@@ -0,0 +1,139 @@
+use crate::{
+    config::{Preset, Weather},
+    failure::{Failure, FailureKind},
+    overrides::OverrideEditor,
+    script::ScriptEditor,
+    time::TimeEditor,
+    weather::{CloudsEditor, QnhEditor, TemperatureEditor, WindEditor},
+};
+use anyhow::Result;
+use rand::rngs::StdRng;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+};
+
+/// A change to the mission file of every generated miz, ie. the start time or the wind
+pub trait MissionEditor {
+    /// Name of the editor in the `editors` list of the `[misc]` options
+    fn name(&self) -> &'static str;
+
+    /// Keys of the preset and weather tables the editor reads its settings from
+    fn config_keys(&self) -> &'static [&'static str];
+
+    /// Applies the editor to the mission file, returning the new mission and the values it set
+    fn apply(&self, mission: &str, context: &mut EditContext) -> Result<Edit>;
+}
+
+/// Everything an editor needs to know about the mission being generated
+pub struct EditContext<'a> {
+    pub preset_name: &'a str,
+    pub preset: &'a Preset,
+    /// Name and settings of the chosen weather preset, if the preset has any
+    pub weather: Option<(&'a str, &'a Weather)>,
+    pub rng: &'a mut StdRng,
+    /// The other Lua files of the miz by entry name (`options`, `warehouses`), which editors
+    /// can modify as well
+    pub files: &'a mut BTreeMap<String, String>,
+    /// Without a mission file, the editors cannot check that the keys they modify exist
+    pub dry_run: bool,
+}
+
+/// The result of applying an editor
+pub struct Edit {
+    pub mission: String,
+    pub changes: Vec<Change>,
+}
+
+impl Edit {
+    /// An edit that leaves the mission as it is
+    pub fn unchanged(mission: &str) -> Self {
+        Edit {
+            mission: mission.to_owned(),
+            changes: Vec::new(),
+        }
+    }
+}
+
+/// A value set by an editor, printed as `   Label:   value`
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub label: String,
+    pub value: String,
+}
+
+impl Change {
+    pub fn new(label: impl Into<String>, value: impl Into<String>) -> Self {
+        Change {
+            label: label.into(),
+            value: value.into(),
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "   {:<23}{}", format!("{}:", self.label), self.value)
+    }
+}
+
+/// The editors available to the repacker, in their default order
+pub struct EditorRegistry {
+    editors: Vec<Box<dyn MissionEditor>>,
+}
+
+impl Default for EditorRegistry {
+    fn default() -> Self {
+        EditorRegistry {
+            editors: vec![
+                Box::new(TimeEditor),
+                Box::new(CloudsEditor),
+                Box::new(WindEditor),
+                Box::new(TemperatureEditor),
+                Box::new(QnhEditor),
+                Box::new(OverrideEditor),
+                Box::new(ScriptEditor),
+            ],
+        }
+    }
+}
+
+impl EditorRegistry {
+    /// Adds an editor after the existing ones, replacing any editor with the same name
+    pub fn register(&mut self, editor: Box<dyn MissionEditor>) {
+        self.editors
+            .retain(|existing| existing.name() != editor.name());
+        self.editors.push(editor);
+    }
+
+    pub fn editors(&self) -> impl Iterator<Item = &dyn MissionEditor> {
+        self.editors.iter().map(|editor| editor.as_ref())
+    }
+
+    /// Returns the editors listed in the configuration in that order, or all of them in
+    /// their default order
+    pub fn ordered(&self, names: Option<&[String]>) -> Result<Vec<&dyn MissionEditor>> {
+        let Some(names) = names else {
+            return Ok(self.editors().collect());
+        };
+
+        names
+            .iter()
+            .map(|name| {
+                self.editors()
+                    .find(|editor| editor.name() == name)
+                    .ok_or_else(|| {
+                        let known: Vec<_> = self.editors().map(|editor| editor.name()).collect();
+                        Failure::new(
+                            FailureKind::Config,
+                            format!(
+                            "Unknown editor '{name}' in the editors option, expected one of: {}",
+                            known.join(", ")
+                        ),
+                        )
+                        .into()
+                    })
+            })
+            .collect()
+    }
+}
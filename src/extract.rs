@@ -0,0 +1,100 @@
+use crate::{
+    failure::{Failure, FailureKind},
+    inspect::open_miz,
+};
+use anyhow::{anyhow, Context, Result};
+use glob::{MatchOptions, Pattern};
+use std::{
+    fs::{create_dir_all, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// The entries extracted from a miz
+#[derive(Debug)]
+pub struct Extracted {
+    pub output_dir: PathBuf,
+    /// Names of the extracted entries, in archive order
+    pub entries: Vec<String>,
+}
+
+/// Extracts the entries of a miz matching any of the given patterns, or all entries if there are none
+pub fn extract_miz(
+    miz_path: &Path,
+    output_dir: Option<&Path>,
+    patterns: &[String],
+) -> Result<Extracted> {
+    let patterns = patterns
+        .iter()
+        .map(|pattern| Pattern::new(pattern).with_context(|| format!("Invalid pattern: {pattern}")))
+        .collect::<Result<Vec<_>>>()?;
+    let match_options = MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+
+    let output_dir = match output_dir {
+        Some(output_dir) => output_dir.to_owned(),
+        None => default_output_dir(miz_path)?,
+    };
+
+    let mut archive = open_miz(miz_path)?;
+
+    let mut entries = Vec::new();
+    for idx in 0..archive.len() {
+        let mut file = archive.by_index(idx)?;
+        if file.is_dir() {
+            continue;
+        }
+
+        let name = file.name().to_owned();
+        if !patterns.is_empty()
+            && !patterns
+                .iter()
+                .any(|pattern| pattern.matches_with(&name, match_options))
+        {
+            continue;
+        }
+
+        // Never write outside of the output directory, even if the archive says so
+        let relative_path = file
+            .enclosed_name()
+            .ok_or_else(|| anyhow!("Refusing to extract unsafe path: {name}"))?
+            .to_owned();
+        let path = output_dir.join(relative_path);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).with_context(|| {
+                Failure::new(
+                    FailureKind::WriteFailed,
+                    format!("Cannot create {}", parent.display()),
+                )
+            })?;
+        }
+
+        File::create(&path)
+            .and_then(|mut output| io::copy(&mut file, &mut output))
+            .with_context(|| {
+                Failure::new(
+                    FailureKind::WriteFailed,
+                    format!("Cannot write {}", path.display()),
+                )
+            })?;
+        entries.push(name);
+    }
+
+    if entries.is_empty() {
+        return Err(anyhow!("No entries in the miz match the given names"));
+    }
+
+    Ok(Extracted {
+        output_dir,
+        entries,
+    })
+}
+
+fn default_output_dir(miz_path: &Path) -> Result<PathBuf> {
+    let stem = miz_path
+        .file_stem()
+        .ok_or_else(|| anyhow!("Cannot find the name of {}", miz_path.display()))?;
+    Ok(miz_path.with_file_name(stem))
+}
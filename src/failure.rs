@@ -0,0 +1,74 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+/// Kind of failure, which decides the exit code of the process
+///
+/// Errors without a kind exit with code 1, and command line usage errors with code 2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The configuration cannot be read or has problems
+    Config,
+    /// A miz, config file or directory given as input does not exist
+    MissingInput,
+    /// The mission does not contain a key the repacker has to modify
+    MissingMissionKey,
+    /// The miz is not a valid archive, or its mission file cannot be read
+    CorruptArchive,
+    /// A generated miz or another output file cannot be written
+    WriteFailed,
+}
+
+impl FailureKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            FailureKind::Config => 3,
+            FailureKind::MissingInput => 4,
+            FailureKind::MissingMissionKey => 5,
+            FailureKind::CorruptArchive => 6,
+            FailureKind::WriteFailed => 7,
+        }
+    }
+
+    /// Returns the kind of the outermost failure in the error chain, if any
+    pub fn of(err: &anyhow::Error) -> Option<FailureKind> {
+        err.downcast_ref::<Failure>().map(|failure| failure.kind)
+    }
+}
+
+/// An error message tagged with the kind of failure
+///
+/// It is either returned as an error or attached to one with `.context()`, and displays as
+/// its message only.
+#[derive(Debug)]
+pub struct Failure {
+    pub kind: FailureKind,
+    message: String,
+}
+
+impl Failure {
+    pub fn new(kind: FailureKind, message: impl Into<String>) -> Self {
+        Failure {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for Failure {}
+
+/// Error for a key the repacker has to modify, but cannot find in the mission file
+pub fn missing_key_error(key: &str) -> anyhow::Error {
+    Failure::new(
+        FailureKind::MissingMissionKey,
+        format!("Could not find {key} in mission file"),
+    )
+    .into()
+}
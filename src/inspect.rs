@@ -0,0 +1,236 @@
+use crate::{
+    failure::{Failure, FailureKind},
+    lua::{parse_lua_assignment, LuaValue},
+    repacker::read_mission_entry,
+};
+use anyhow::{Context, Result};
+use serde_derive::Serialize;
+use std::{
+    fmt::{self, Display},
+    fs::File,
+    path::Path,
+};
+use zip::ZipArchive;
+
+/// The environment settings of a mission
+#[derive(Debug, Serialize)]
+pub struct MissionEnvironment {
+    pub theatre: Option<String>,
+    pub date: Option<String>,
+    pub start_time: Option<String>,
+    pub cloud_preset: Option<String>,
+    pub cloud_base: Option<f64>,
+    pub wind_ground: Wind,
+    pub wind_2000m: Wind,
+    pub wind_8000m: Wind,
+    pub temperature: Option<f64>,
+    pub qnh: Option<f64>,
+    pub fog_enabled: Option<bool>,
+    pub fog_visibility: Option<f64>,
+    pub fog_thickness: Option<f64>,
+    pub dust_enabled: Option<bool>,
+    pub dust_density: Option<f64>,
+    pub visibility: Option<f64>,
+    pub required_modules: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Wind {
+    pub speed: Option<f64>,
+    pub heading: Option<f64>,
+}
+
+/// Opens a miz archive given as input
+pub fn open_miz(miz_path: &Path) -> Result<ZipArchive<File>> {
+    let file = File::open(miz_path).with_context(|| {
+        Failure::new(
+            FailureKind::MissingInput,
+            format!("Cannot open {}", miz_path.display()),
+        )
+    })?;
+    ZipArchive::new(file).with_context(|| {
+        Failure::new(
+            FailureKind::CorruptArchive,
+            format!("Cannot read {} as a miz archive", miz_path.display()),
+        )
+    })
+}
+
+/// Reads the `mission` file of a miz archive
+pub fn read_mission(miz_path: &Path) -> Result<String> {
+    let mut archive = open_miz(miz_path)?;
+    read_mission_entry(&mut archive).with_context(|| {
+        Failure::new(
+            FailureKind::CorruptArchive,
+            format!("Cannot read the mission file of {}", miz_path.display()),
+        )
+    })
+}
+
+impl MissionEnvironment {
+    pub fn from_mission(mission: &LuaValue) -> Self {
+        let number = |path| mission.get_path(path).and_then(LuaValue::as_f64);
+        let string = |path| {
+            mission
+                .get_path(path)
+                .and_then(LuaValue::as_str)
+                .map(str::to_owned)
+        };
+        let bool = |path| mission.get_path(path).and_then(LuaValue::as_bool);
+        let wind = |altitude: &str| {
+            let wind = mission.get_path(&format!("weather.wind.{altitude}"));
+            Wind {
+                speed: wind.and_then(|wind| wind.get("speed")?.as_f64()),
+                heading: wind.and_then(|wind| wind.get("dir")?.as_f64()),
+            }
+        };
+
+        let date = match (
+            number("date.Year"),
+            number("date.Month"),
+            number("date.Day"),
+        ) {
+            (Some(year), Some(month), Some(day)) => Some(format!("{year}-{month:02}-{day:02}")),
+            _ => None,
+        };
+
+        let start_time = number("start_time").map(|start_time| {
+            let seconds = start_time as i64;
+            format!(
+                "{:02}:{:02}:{:02}",
+                seconds / 3600 % 24,
+                seconds / 60 % 60,
+                seconds % 60
+            )
+        });
+
+        let required_modules = mission
+            .get("requiredModules")
+            .and_then(LuaValue::as_table)
+            .map(|modules| modules.iter().map(|(key, _)| key.to_string()).collect())
+            .unwrap_or_default();
+
+        MissionEnvironment {
+            theatre: string("theatre"),
+            date,
+            start_time,
+            cloud_preset: string("weather.clouds.preset"),
+            cloud_base: number("weather.clouds.base"),
+            wind_ground: wind("atGround"),
+            wind_2000m: wind("at2000"),
+            wind_8000m: wind("at8000"),
+            temperature: number("weather.season.temperature"),
+            qnh: number("weather.qnh"),
+            fog_enabled: bool("weather.enable_fog"),
+            fog_visibility: number("weather.fog.visibility"),
+            fog_thickness: number("weather.fog.thickness"),
+            dust_enabled: bool("weather.enable_dust"),
+            dust_density: number("weather.dust_density"),
+            visibility: number("weather.visibility.distance"),
+            required_modules,
+        }
+    }
+}
+
+impl Display for MissionEnvironment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn show<T: ToString>(value: &Option<T>, unit: &str) -> String {
+            match value {
+                Some(value) => format!("{}{unit}", value.to_string()),
+                None => String::from("-"),
+            }
+        }
+        fn show_wind(wind: &Wind) -> String {
+            format!(
+                "{} at {}",
+                show(&wind.speed, " m/s"),
+                show(&wind.heading, "°")
+            )
+        }
+
+        writeln!(f, "   Theatre:               {}", show(&self.theatre, ""))?;
+        writeln!(f, "   Date:                  {}", show(&self.date, ""))?;
+        writeln!(
+            f,
+            "   Start time:            {}",
+            show(&self.start_time, "")
+        )?;
+        writeln!(
+            f,
+            "   Cloud preset:          {}",
+            show(&self.cloud_preset, "")
+        )?;
+        writeln!(
+            f,
+            "   Cloud base:            {}",
+            show(&self.cloud_base, " meters")
+        )?;
+        writeln!(
+            f,
+            "   Ground wind:           {}",
+            show_wind(&self.wind_ground)
+        )?;
+        writeln!(
+            f,
+            "   2000m wind:            {}",
+            show_wind(&self.wind_2000m)
+        )?;
+        writeln!(
+            f,
+            "   8000m wind:            {}",
+            show_wind(&self.wind_8000m)
+        )?;
+        writeln!(
+            f,
+            "   Temperature:           {}",
+            show(&self.temperature, " °C")
+        )?;
+        writeln!(f, "   QNH:                   {}", show(&self.qnh, " mmHg"))?;
+        writeln!(
+            f,
+            "   Fog enabled:           {}",
+            show(&self.fog_enabled, "")
+        )?;
+        writeln!(
+            f,
+            "   Fog visibility:        {}",
+            show(&self.fog_visibility, " meters")
+        )?;
+        writeln!(
+            f,
+            "   Fog thickness:         {}",
+            show(&self.fog_thickness, " meters")
+        )?;
+        writeln!(
+            f,
+            "   Dust enabled:          {}",
+            show(&self.dust_enabled, "")
+        )?;
+        writeln!(
+            f,
+            "   Dust density:          {}",
+            show(&self.dust_density, " meters")
+        )?;
+        writeln!(
+            f,
+            "   Visibility:            {}",
+            show(&self.visibility, " meters")
+        )?;
+        if self.required_modules.is_empty() {
+            write!(f, "   Required modules:      none")
+        } else {
+            write!(
+                f,
+                "   Required modules:      {}",
+                self.required_modules.join(", ")
+            )
+        }
+    }
+}
+
+/// Reads the environment settings of a miz
+pub fn inspect_miz(miz_path: &Path) -> Result<MissionEnvironment> {
+    let mission = read_mission(miz_path)?;
+    let (_, mission) = parse_lua_assignment(&mission).context("Cannot read mission file")?;
+    Ok(MissionEnvironment::from_mission(&mission))
+}
@@ -0,0 +1,28 @@
+//! Generates DCS missions with different times and weather from a single miz
+//!
+//! The `Repacker` applies the presets of a `Config` to a miz and returns the generated
+//! archives, which the `dcs-miz-repacker` executable writes next to the source mission.
+
+pub mod config;
+pub mod diff;
+pub mod editor;
+pub mod extract;
+pub mod failure;
+pub mod inspect;
+pub mod lua;
+pub mod misc;
+pub mod output;
+pub mod overrides;
+pub mod repacker;
+pub mod report;
+pub mod script;
+pub mod template;
+pub mod time;
+pub mod weather;
+
+pub use config::Config;
+pub use repacker::{GeneratedMission, GeneratedMiz, Repacker};
+
+pub fn flip_heading(heading: i32) -> i32 {
+    (heading + 180) % 360
+}
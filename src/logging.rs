@@ -0,0 +1,99 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+};
+
+/// Prints messages of this crate to the console, and optionally appends them to a log file
+struct Logger {
+    console_level: LevelFilter,
+    file_level: LevelFilter,
+    file: Option<Mutex<File>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+            && (metadata.level() <= self.console_level
+                || self.file.is_some() && metadata.level() <= self.file_level)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+
+        if record.level() <= self.console_level {
+            match record.level() {
+                Level::Error => eprintln!("{message}"),
+                Level::Warn => println!("?> {message}"),
+                _ => println!("{message}"),
+            }
+        }
+
+        // Blank lines only separate sections on the console
+        if let Some(file) = &self.file {
+            if record.level() <= self.file_level && !message.trim().is_empty() {
+                let mut file = file.lock().unwrap();
+                let _ = writeln!(
+                    file,
+                    "{} {:<5} {}",
+                    Local::now().format("%Y-%m-%d %H:%M:%S"),
+                    record.level(),
+                    message.trim_end()
+                );
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+/// Returns the console log level for the `-q` and `-v` flags
+pub fn console_level(quiet: bool, verbose: u8) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+/// Sets up logging, appending to the log file if one is given
+///
+/// The log file records at least the normal progress messages, even with `-q`, so that
+/// unattended runs leave a record of what they did.
+pub fn init(console_level: LevelFilter, log_file: Option<&Path>) -> Result<()> {
+    let file_level = console_level.max(LevelFilter::Info);
+    let file = log_file
+        .map(|path| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Cannot open log file {}", path.display()))
+        })
+        .transpose()?;
+
+    let max_level = if file.is_some() {
+        file_level
+    } else {
+        console_level
+    };
+    log::set_boxed_logger(Box::new(Logger {
+        console_level,
+        file_level,
+        file: file.map(Mutex::new),
+    }))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
@@ -0,0 +1,526 @@
+use anyhow::{anyhow, Result};
+use std::fmt::{self, Display};
+
+/// A value of the Lua tables DCS stores inside the miz (`mission`, `options`, `warehouses`...)
+#[derive(Debug, Clone, PartialEq)]
+pub enum LuaValue {
+    Nil,
+    Bool(bool),
+    /// A number written without a fraction or exponent, kept exact since unit and group ids
+    /// can be larger than what a `f64` holds
+    Integer(i64),
+    Number(f64),
+    String(String),
+    Table(LuaTable),
+}
+
+/// Key of a Lua table entry
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LuaKey {
+    Index(i64),
+    Name(String),
+}
+
+/// Entries of a Lua table, in the order they appear in the file
+pub type LuaTable = Vec<(LuaKey, LuaValue)>;
+
+impl LuaValue {
+    /// Returns the value at a dot-separated key path, ie. `weather.clouds.preset`
+    ///
+    /// Numeric path segments match both integer and string keys.
+    pub fn get_path(&self, path: &str) -> Option<&LuaValue> {
+        path.split('.')
+            .try_fold(self, |value, segment| value.get(segment))
+    }
+
+    /// Returns the value of a table entry
+    pub fn get(&self, key: &str) -> Option<&LuaValue> {
+        match self {
+            LuaValue::Table(table) => table
+                .iter()
+                .find(|(entry_key, _)| entry_key.matches(key))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            LuaValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            LuaValue::Integer(value) => Some(*value as f64),
+            LuaValue::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            LuaValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&LuaTable> {
+        match self {
+            LuaValue::Table(table) => Some(table),
+            _ => None,
+        }
+    }
+}
+
+impl LuaKey {
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            LuaKey::Index(index) => key.parse() == Ok(*index),
+            LuaKey::Name(name) => name == key,
+        }
+    }
+}
+
+impl Display for LuaKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LuaKey::Index(index) => write!(f, "{index}"),
+            LuaKey::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl Display for LuaValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LuaValue::Nil => write!(f, "nil"),
+            LuaValue::Bool(value) => write!(f, "{value}"),
+            LuaValue::Integer(value) => write!(f, "{value}"),
+            LuaValue::Number(value) => write!(f, "{value}"),
+            LuaValue::String(value) => write!(f, "{value:?}"),
+            LuaValue::Table(table) => write!(f, "{{{} entries}}", table.len()),
+        }
+    }
+}
+
+/// Parses a Lua file consisting of a single global assignment, ie. `mission = { ... }`,
+/// returning the name of the global and its value
+pub fn parse_lua_assignment(source: &str) -> Result<(String, LuaValue)> {
+    let mut parser = Parser {
+        source: source.as_bytes(),
+        pos: 0,
+    };
+
+    let name = parser.identifier()?;
+    parser.expect(b'=')?;
+    let value = parser.value()?;
+
+    parser.skip_whitespace();
+    if parser.pos < parser.source.len() {
+        return Err(parser.error("expected end of file"));
+    }
+
+    Ok((name, value))
+}
+
+/// Writes a global assignment in the layout DCS uses for the files inside the miz,
+/// ie. `mission = { ... } -- end of mission`
+pub fn to_lua_assignment(name: &str, value: &LuaValue) -> String {
+    let mut output = format!("{name} = ");
+    match value {
+        LuaValue::Table(table) => {
+            write_table(&mut output, table, 0);
+            output.push_str(&format!(" -- end of {name}\n"));
+        }
+        value => {
+            write_value(&mut output, value, 0);
+            output.push('\n');
+        }
+    }
+    output
+}
+
+fn write_table(output: &mut String, table: &LuaTable, depth: usize) {
+    let indent = "    ".repeat(depth);
+    output.push_str(&format!("\n{indent}{{\n"));
+    for (key, value) in table {
+        let key = match key {
+            LuaKey::Index(index) => format!("[{index}]"),
+            LuaKey::Name(name) => format!("[{}]", quote(name)),
+        };
+        output.push_str(&format!("{indent}    {key} = "));
+        write_value(output, value, depth + 1);
+        if matches!(value, LuaValue::Table(_)) {
+            output.push_str(&format!(", -- end of {key}\n"));
+        } else {
+            output.push_str(",\n");
+        }
+    }
+    output.push_str(&format!("{indent}}}"));
+}
+
+fn write_value(output: &mut String, value: &LuaValue, depth: usize) {
+    match value {
+        LuaValue::Nil => output.push_str("nil"),
+        LuaValue::Bool(value) => output.push_str(&value.to_string()),
+        LuaValue::Integer(value) => output.push_str(&value.to_string()),
+        LuaValue::Number(value) => output.push_str(&value.to_string()),
+        LuaValue::String(value) => output.push_str(&quote(value)),
+        LuaValue::Table(table) => write_table(output, table, depth),
+    }
+}
+
+/// Quotes a string the way DCS does, with escaped newlines spanning multiple lines
+fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for char in value.chars() {
+        match char {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\\n"),
+            '\r' => quoted.push_str("\\r"),
+            char if char.is_ascii_control() => quoted.push_str(&format!("\\{:03}", char as u8)),
+            char => quoted.push(char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+struct Parser<'a> {
+    source: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> anyhow::Error {
+        let line = self.source[..self.pos.min(self.source.len())]
+            .iter()
+            .filter(|&&byte| byte == b'\n')
+            .count()
+            + 1;
+        anyhow!("Cannot parse Lua table, {message} on line {line}")
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.get(self.pos).copied()
+    }
+
+    /// Skips whitespace and `--` comments
+    fn skip_whitespace(&mut self) {
+        loop {
+            match self.peek() {
+                Some(byte) if byte.is_ascii_whitespace() => self.pos += 1,
+                Some(b'-') if self.source.get(self.pos + 1) == Some(&b'-') => {
+                    while !matches!(self.peek(), None | Some(b'\n')) {
+                        self.pos += 1;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<()> {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", expected as char)))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while matches!(self.peek(), Some(byte) if byte.is_ascii_alphanumeric() || byte == b'_') {
+            self.pos += 1;
+        }
+        if start == self.pos || self.source[start].is_ascii_digit() {
+            return Err(self.error("expected identifier"));
+        }
+        Ok(String::from_utf8_lossy(&self.source[start..self.pos]).into_owned())
+    }
+
+    fn value(&mut self) -> Result<LuaValue> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.table(),
+            Some(b'"' | b'\'') => Ok(LuaValue::String(self.string()?)),
+            Some(byte) if byte == b'-' || byte == b'.' || byte.is_ascii_digit() => self.number(),
+            Some(_) => match self.identifier()?.as_str() {
+                "true" => Ok(LuaValue::Bool(true)),
+                "false" => Ok(LuaValue::Bool(false)),
+                "nil" => Ok(LuaValue::Nil),
+                _ => Err(self.error("expected value")),
+            },
+            None => Err(self.error("unexpected end of file")),
+        }
+    }
+
+    fn table(&mut self) -> Result<LuaValue> {
+        self.expect(b'{')?;
+        let mut table = LuaTable::new();
+        let mut next_index = 1;
+
+        loop {
+            self.skip_whitespace();
+            let key = match self.peek() {
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(LuaValue::Table(table));
+                }
+                Some(b'[') => {
+                    self.pos += 1;
+                    let key = match self.value()? {
+                        LuaValue::String(name) => LuaKey::Name(name),
+                        LuaValue::Integer(index) => LuaKey::Index(index),
+                        LuaValue::Number(index) if index.fract() == 0.0 => {
+                            LuaKey::Index(index as i64)
+                        }
+                        _ => return Err(self.error("unsupported table key")),
+                    };
+                    self.expect(b']')?;
+                    self.expect(b'=')?;
+                    key
+                }
+                Some(byte) if byte.is_ascii_alphabetic() || byte == b'_' => {
+                    // Either a `name = value` entry, or a positional true/false/nil
+                    let start = self.pos;
+                    let name = self.identifier()?;
+                    self.skip_whitespace();
+                    if self.peek() == Some(b'=') {
+                        self.pos += 1;
+                        LuaKey::Name(name)
+                    } else {
+                        self.pos = start;
+                        LuaKey::Index(next_index)
+                    }
+                }
+                _ => LuaKey::Index(next_index),
+            };
+
+            if key == LuaKey::Index(next_index) {
+                next_index += 1;
+            }
+            table.push((key, self.value()?));
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',' | b';') => self.pos += 1,
+                Some(b'}') => {}
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let quote = self.source[self.pos];
+        self.pos += 1;
+        let mut bytes = Vec::new();
+
+        loop {
+            let byte = self
+                .peek()
+                .ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += 1;
+            match byte {
+                _ if byte == quote => break,
+                b'\\' => {
+                    let escaped = self
+                        .peek()
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += 1;
+                    match escaped {
+                        b'n' | b'\n' => bytes.push(b'\n'),
+                        b'r' => bytes.push(b'\r'),
+                        b't' => bytes.push(b'\t'),
+                        b'a' => bytes.push(0x07),
+                        b'b' => bytes.push(0x08),
+                        b'f' => bytes.push(0x0c),
+                        b'v' => bytes.push(0x0b),
+                        b'0'..=b'9' => {
+                            // Up to three decimal digits
+                            let start = self.pos - 1;
+                            while self.pos - start < 3 && matches!(self.peek(), Some(b'0'..=b'9')) {
+                                self.pos += 1;
+                            }
+                            let code: u32 =
+                                std::str::from_utf8(&self.source[start..self.pos])?.parse()?;
+                            let code =
+                                u8::try_from(code).map_err(|_| self.error("invalid escape"))?;
+                            bytes.push(code);
+                        }
+                        other => bytes.push(other),
+                    }
+                }
+                _ => bytes.push(byte),
+            }
+        }
+
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8 in string"))
+    }
+
+    fn number(&mut self) -> Result<LuaValue> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        while let Some(byte) = self.peek() {
+            let is_exponent_sign =
+                (byte == b'-' || byte == b'+') && matches!(self.source[self.pos - 1], b'e' | b'E');
+            if byte.is_ascii_alphanumeric() || byte == b'.' || is_exponent_sign {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+
+        let text = std::str::from_utf8(&self.source[start..self.pos])?;
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text),
+        };
+        let value = match digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"))
+        {
+            Some(hex) => i64::from_str_radix(hex, 16).map(LuaValue::Integer).ok(),
+            // Integers too large for an i64 fall back to a float, same as in Lua
+            None => digits
+                .parse()
+                .map(LuaValue::Integer)
+                .or_else(|_| digits.parse().map(LuaValue::Number))
+                .ok(),
+        }
+        .ok_or_else(|| self.error(&format!("invalid number '{text}'")))?;
+
+        Ok(match value {
+            LuaValue::Integer(value) if negative => LuaValue::Integer(-value),
+            LuaValue::Number(value) if negative => LuaValue::Number(-value),
+            value => value,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MISSION: &str = r#"mission = 
+{
+    ["date"] = 
+    {
+        ["Day"] = 1,
+        ["Year"] = 2024,
+    }, -- end of ["date"]
+    ["start_time"] = 28800,
+    ["weather"] = 
+    {
+        ["qnh"] = 760.5,
+        ["atmosphere_type"] = 0,
+        ["enable_fog"] = false,
+    }, -- end of ["weather"]
+    ["groupId"] = 9007199254740993,
+    ["descriptionText"] = "Line \"one\"\
+Line two\\",
+    ["units"] = 
+    {
+        [1] = 
+        {
+            ["name"] = "Unit #1",
+        }, -- end of [1]
+        [2] = 
+        {
+            ["name"] = "Unit #2",
+        }, -- end of [2]
+    }, -- end of ["units"]
+} -- end of mission
+"#;
+
+    #[test]
+    fn round_trips_dcs_files() {
+        let (name, value) = parse_lua_assignment(MISSION).unwrap();
+        assert_eq!(name, "mission");
+        assert_eq!(to_lua_assignment(&name, &value), MISSION);
+    }
+
+    #[test]
+    fn reads_nested_values() {
+        let (_, value) = parse_lua_assignment(MISSION).unwrap();
+        assert_eq!(
+            value.get_path("weather.qnh"),
+            Some(&LuaValue::Number(760.5))
+        );
+        assert_eq!(
+            value.get_path("units.2.name").and_then(LuaValue::as_str),
+            Some("Unit #2")
+        );
+        assert_eq!(
+            value.get_path("descriptionText").and_then(LuaValue::as_str),
+            Some("Line \"one\"\nLine two\\")
+        );
+    }
+
+    #[test]
+    fn keeps_integers_exact() {
+        let (_, value) = parse_lua_assignment(MISSION).unwrap();
+        assert_eq!(
+            value.get("groupId"),
+            Some(&LuaValue::Integer(9_007_199_254_740_993))
+        );
+
+        let (_, value) =
+            parse_lua_assignment("value = { -12, 0x1F, 1e3, 99999999999999999999 }").unwrap();
+        let values: Vec<_> = value.as_table().unwrap().iter().map(|(_, v)| v).collect();
+        assert_eq!(
+            values,
+            [
+                &LuaValue::Integer(-12),
+                &LuaValue::Integer(31),
+                &LuaValue::Number(1000.0),
+                &LuaValue::Number(1e20),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_hand_written_tables() {
+        let (name, value) =
+            parse_lua_assignment("options = { 'a', difficulty = { labels = 0; }, true, nil }")
+                .unwrap();
+        assert_eq!(name, "options");
+        assert_eq!(
+            value,
+            LuaValue::Table(vec![
+                (LuaKey::Index(1), LuaValue::String("a".to_owned())),
+                (
+                    LuaKey::Name("difficulty".to_owned()),
+                    LuaValue::Table(vec![(
+                        LuaKey::Name("labels".to_owned()),
+                        LuaValue::Integer(0)
+                    )])
+                ),
+                (LuaKey::Index(2), LuaValue::Bool(true)),
+                (LuaKey::Index(3), LuaValue::Nil),
+            ])
+        );
+    }
+
+    #[test]
+    fn escapes_control_characters() {
+        let value = LuaValue::String("tab\there\r".to_owned());
+        let source = to_lua_assignment("text", &value);
+        assert_eq!(source, "text = \"tab\\009here\\r\"\n");
+        assert_eq!(parse_lua_assignment(&source).unwrap().1, value);
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        let err = parse_lua_assignment("mission = \n{\n    [\"a\"] = ,\n}").unwrap_err();
+        assert!(err.to_string().ends_with("on line 3"), "{err}");
+    }
+}
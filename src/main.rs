@@ -1,181 +1,253 @@
-mod config;
-mod misc;
-mod time;
-mod weather;
+mod logging;
 
-use crate::{misc::remove_required_modules, time::modify_time, weather::modify_weather};
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
-use config::{read_config, Config};
+use chrono::Local;
+use clap::{
+    error::ErrorKind, parser::ValueSource, ArgAction, Args, CommandFactory, FromArgMatches, Parser,
+    Subcommand, ValueEnum,
+};
 use crossterm::{
     event::{self, Event},
     terminal,
     tty::IsTty,
 };
-use once_cell::sync::Lazy;
-use rand::{seq::SliceRandom, thread_rng};
-use regex::{Captures, Regex};
+use dcs_miz_repacker::{
+    config::{config_sources, read_config, Config, MiscOptions, PauseMode},
+    diff::{diff_lua, diff_miz},
+    editor::{Change, EditorRegistry},
+    extract::extract_miz,
+    failure::{Failure, FailureKind},
+    inspect::{inspect_miz, MissionEnvironment},
+    lua::{parse_lua_assignment, LuaValue},
+    output::{backup_previous_output, output_path, OutputVars, BACKUP_DIR},
+    repacker::{read_embedded_config, read_mission_entry},
+    report::{MissionReport, PresetReport, RunReport},
+    Repacker,
+};
+use log::{debug, error, info, warn};
+use rand::{thread_rng, Rng};
 use std::{
-    collections::HashSet,
-    env::{current_exe, set_current_dir},
-    fs::File,
-    io::{self, stdout, BufRead, BufReader, Read, Write},
-    path::{Path, PathBuf},
+    collections::{HashMap, HashSet},
+    env::{args_os, current_exe, set_current_dir},
+    ffi::OsString,
+    fs::{create_dir_all, File},
+    io::{stdout, BufRead, BufReader, Write},
+    path::{absolute, Path, PathBuf},
     process::exit,
     thread::{sleep, spawn},
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 use walkdir::WalkDir;
-use zip::{write::FileOptions, ZipArchive, ZipWriter};
+use zip::ZipArchive;
 
-pub fn flip_heading(heading: i32) -> i32 {
-    (heading + 180) % 360
+/// What `repack_miz` does with the generated missions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RunMode {
+    /// Write the generated missions
+    Write,
+    /// Read the miz and report the changes, without writing anything
+    DryRun,
+    /// Only sample the configured values, without reading or writing any miz
+    Preview,
 }
 
-fn splice_filename(path: &str, new_suffix: &str, dry_run: bool) -> Result<String> {
-    if dry_run {
-        return Ok(String::from(path));
-    }
+/// Writes a generated miz, backing up the previous version first
+fn write_miz(path: &Path, data: &[u8], backup_count: usize) -> Result<()> {
+    let output_dir = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    create_dir_all(output_dir).with_context(|| {
+        Failure::new(
+            FailureKind::WriteFailed,
+            format!("Cannot create {}", output_dir.display()),
+        )
+    })?;
 
-    static REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(.+)\.(.+)$").unwrap());
-    if REGEX.is_match(path) {
-        Ok(REGEX
-            .replace(path, |caps: &Captures| {
-                format!("{}_{}.{}", &caps[1], new_suffix, &caps[2])
-            })
-            .into_owned())
-    } else {
-        Err(anyhow!("File extension missing"))
-    }
-}
+    // Write to a temporary file first, so that a failure halfway never leaves a
+    // truncated miz in place. The temporary file is deleted if it is dropped.
+    let write_failed = || {
+        Failure::new(
+            FailureKind::WriteFailed,
+            format!("Cannot write {}", path.display()),
+        )
+    };
+    let mut temp_file = tempfile::Builder::new()
+        .prefix(".repack")
+        .suffix(".tmp")
+        .tempfile_in(output_dir)
+        .with_context(|| {
+            Failure::new(
+                FailureKind::WriteFailed,
+                format!("Cannot create temporary file in {}", output_dir.display()),
+            )
+        })?;
+    temp_file.write_all(data).with_context(write_failed)?;
 
-fn add_file(
-    zip: &mut ZipWriter<File>,
-    path: &str,
-    data: &mut dyn Read,
-    added_files: &mut HashSet<String>,
-) -> Result<()> {
-    let path = &path.replace('\\', "/");
-    if !added_files.contains(path) {
-        zip.start_file(path, FileOptions::default().compression_level(Some(9)))?;
-        added_files.insert(path.to_owned());
-        io::copy(data, zip)?;
-    }
+    backup_previous_output(path, backup_count).with_context(|| {
+        Failure::new(
+            FailureKind::WriteFailed,
+            "Failed to back up the previous version",
+        )
+    })?;
+    temp_file.persist(path).with_context(write_failed)?;
     Ok(())
 }
 
-fn add_repack_files(zip: &mut ZipWriter<File>, added_files: &mut HashSet<String>) -> Result<()> {
-    let path = Path::new("repack");
-    if path.is_dir() {
-        println!("-> Repacking files from repack directory");
-        for entry in WalkDir::new(path) {
-            let entry = entry?;
-            let fs_path = entry.path();
-            if fs_path.is_file() {
-                let zip_path = entry.path().strip_prefix("repack")?;
-                let zip_path_str = zip_path
-                    .to_str()
-                    .ok_or_else(|| anyhow!("Cannot repack non UTF-8 path: {}", path.display()))?;
-                add_file(
-                    zip,
-                    zip_path_str,
-                    &mut File::open(fs_path)
-                        .context(format!("Trying to open '{}' as file", fs_path.display()))?,
-                    added_files,
-                )?;
-                println!("   Repacked {}", zip_path_str);
-            }
-        }
+/// Makes sure that a generated mission never replaces the source miz or another preset
+fn check_output_path(
+    output_path: &Path,
+    source_path: &Path,
+    output_paths: &mut HashSet<PathBuf>,
+) -> Result<()> {
+    let refuse = |reason: &str| {
+        Err(Failure::new(
+            FailureKind::Config,
+            format!(
+                "Refusing to write {}: {reason}, check output_name and output_dir",
+                output_path.display()
+            ),
+        )
+        .into())
+    };
+    if output_path.exists() && output_path.canonicalize()? == source_path {
+        return refuse("it is the source miz");
+    }
+    if !output_paths.insert(absolute(output_path)?) {
+        return refuse("another preset already writes to it");
     }
     Ok(())
 }
 
-fn repack_miz(path: &str, mut config: Config, dry_run: bool) -> Result<()> {
-    println!("Processing {path}...");
-    let mut mission = String::new();
-    let mut archive;
-    let rng = &mut thread_rng();
+/// Generates every preset of the miz, recording the generated missions in the report
+fn repack_miz(
+    path: &str,
+    config: Config,
+    seed: u64,
+    mode: RunMode,
+    report: &mut MissionReport,
+) -> Result<()> {
+    info!("Processing {path}...");
+    info!("Random seed: {seed}");
+    let misc = config.misc.clone();
+    let mut repacker = Repacker::new(config, seed)?;
+    let timestamp = Local::now();
 
-    if dry_run {
-        archive = None;
-    } else {
-        archive = Some(ZipArchive::new(File::open(path)?)?);
-        archive
-            .as_mut()
-            .unwrap()
-            .by_name("mission")?
-            .read_to_string(&mut mission)?;
+    if mode == RunMode::Preview {
+        for preset_name in repacker.preset_names() {
+            repacker.preview(&preset_name)?;
+            info!("-> Done\n");
+        }
+        info!("All done!\n");
+        return Ok(());
     }
 
-    if config.misc.remove_required_modules {
-        mission = remove_required_modules(&mission, dry_run)?;
-    }
+    let corrupt = || {
+        Failure::new(
+            FailureKind::CorruptArchive,
+            format!("Cannot read the mission file of {path}"),
+        )
+    };
 
-    for (name, preset) in &config.preset {
-        let new_path = splice_filename(path, name, dry_run)?;
-        let mut out_mission = mission.clone();
-        println!("-> Generating miz preset: {name}");
-        out_mission = modify_time(&out_mission, preset, dry_run)?;
+    // The dry run compares the generated files to the original ones
+    let originals = match mode {
+        RunMode::DryRun => {
+            let mut archive = ZipArchive::new(File::open(path)?).with_context(corrupt)?;
+            let mission = read_mission_entry(&mut archive).with_context(corrupt)?;
+            let mission = parse_lua_assignment(&mission).with_context(corrupt)?.1;
+            Some((mission, repacker.read_lua_files(&mut archive)?))
+        }
+        _ => None,
+    };
 
-        // Optionally, modify weather settings in the mission
-        if let Some(weather_presets) = &preset.weather {
-            for preset_name in weather_presets {
-                if config.weather.get(preset_name).is_none() {
-                    return Err(anyhow!("Weather preset not found: {preset_name}"));
-                }
-            }
+    let source_path = Path::new(path).canonicalize()?;
+    let mut output_paths = HashSet::new();
+    repacker.repack_each(File::open(path)?, report, |generated_miz, report| {
+        let generated = &generated_miz.mission;
+        let vars = OutputVars {
+            preset: &generated.preset,
+            weather: generated.weather.as_deref(),
+            seed,
+            timestamp,
+        };
+        let new_path = output_path(path, &misc, &vars)?;
+        check_output_path(&new_path, &source_path, &mut output_paths)?;
 
-            let preset_name = weather_presets
-                .choose_weighted(rng, |weather| config.weather.get(weather).unwrap().weight)?;
+        // Read back the generated values, for the dry run changes and the report
+        let new_mission = parse_lua_assignment(&generated.mission).map(|(_, value)| value);
+        let values = match &new_mission {
+            Ok(new_mission) => Some(MissionEnvironment::from_mission(new_mission)),
+            Err(err) => {
+                report.warn(format!("Cannot read the generated mission values: {err}"));
+                None
+            }
+        };
+        report.presets.push(PresetReport {
+            preset: generated.preset.clone(),
+            weather: generated.weather.clone(),
+            output_path: new_path.clone(),
+            written: false,
+            values,
+        });
 
-            println!("-> Using weather preset:  {preset_name}");
-            let weather = config.weather.get_mut(preset_name).unwrap();
+        if let Some((original_mission, original_files)) = &originals {
+            info!("-> Would write new miz: {}", new_path.display());
+            for name in &generated_miz.removed {
+                info!("   Would remove {name}");
+            }
 
-            weather.randomize_wind_flip();
-            if preset.flip_wind {
-                weather.is_wind_flipped = !weather.is_wind_flipped
+            let new_mission = new_mission.context("Cannot read generated mission file")?;
+            let changes = diff_lua(original_mission, &new_mission);
+            info!("-> Mission changes:");
+            for change in &changes {
+                info!("{change}");
+            }
+            if changes.is_empty() {
+                info!("   No changes");
             }
 
-            out_mission = modify_weather(out_mission, preset_name, weather, dry_run)?;
+            for (name, contents) in &generated.files {
+                let original = original_files.get(name);
+                if original == Some(contents) {
+                    continue;
+                }
+                let read = |source| {
+                    parse_lua_assignment(source)
+                        .map(|(_, value)| value)
+                        .with_context(|| format!("Cannot read {name} file"))
+                };
+                // Files only found in the repack directories are compared to an empty table
+                let original = match original {
+                    Some(original) => read(original)?,
+                    None => LuaValue::Table(Vec::new()),
+                };
+                info!("-> Changes to {name}:");
+                for change in diff_lua(&original, &read(contents)?) {
+                    info!("{change}");
+                }
+            }
         }
 
-        if !dry_run {
-            println!("-> Writing new miz: {new_path}");
-            let mut zip = ZipWriter::new(File::create(&new_path)?);
-            let mut added_files = HashSet::new();
-            let archive = archive.as_mut().unwrap();
-
-            // Copy the modified mission file
-            add_file(
-                &mut zip,
-                "mission",
-                &mut out_mission.as_bytes(),
-                &mut added_files,
-            )?;
-
-            // Copy files from the repack dir
-            add_repack_files(&mut zip, &mut added_files)?;
-
-            // Copy remaining miz files into the new zip
-            for idx in 0..archive.len() {
-                let mut file = archive.by_index(idx)?;
-                let path = file.name().to_owned();
-                add_file(&mut zip, &path, &mut file, &mut added_files)?;
+        if mode == RunMode::Write {
+            info!("-> Writing new miz: {}", new_path.display());
+            for name in &generated_miz.removed {
+                info!("   Removed {name}");
             }
-
-            zip.finish()?;
+            write_miz(&new_path, &generated_miz.data, misc.backup_count)?;
+            report.presets.last_mut().unwrap().written = true;
         }
-        println!("-> Done\n");
-    }
+        info!("-> Done\n");
+        Ok(())
+    })?;
 
-    if !dry_run {
-        println!("Writing current path to \"most recently accessed\" file...");
+    if mode == RunMode::Write {
+        debug!("Writing current path to \"most recently accessed\" file...");
         let mut recent_file = File::create(recent_file_path()?)?;
         write!(recent_file, "{}", Path::new(path).canonicalize()?.display())?;
         recent_file.flush()?;
     }
 
-    println!("All done!\n");
+    info!("All done!\n");
     Ok(())
 }
 
@@ -187,79 +259,720 @@ fn recent_file_path() -> Result<PathBuf> {
 }
 
 fn miz_not_found_error<T>() -> Result<T> {
-    Err(anyhow!(concat!(
-        ".miz file not provided and no recent file was found\n",
-        "Drag and drop a .miz file into the exe to run it"
-    )))
+    Err(Failure::new(
+        FailureKind::MissingInput,
+        concat!(
+            ".miz file not provided and no recent file was found\n",
+            "Drag and drop a .miz file into the exe to run it"
+        ),
+    )
+    .into())
 }
 
 #[derive(Parser, Debug)]
 #[clap(version)]
-struct Args {
-    /// Optional: relative or absolute path of the DCS mission file to repack
-    ///
-    /// If the path is missing, the last repacked miz, as recorded in `repacker_recent.txt`, is used instead
-    miz_path: Option<String>,
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Arguments of the default `repack` command, used when only paths are given
+    #[clap(flatten)]
+    repack: RepackArgs,
 
     /// Run and then exit immediately, without waiting for user input at the end
-    #[clap(long, short)]
+    ///
+    /// Same as `--pause never`.
+    #[clap(long, short, global = true)]
     batch: bool,
 
-    /// Run without actually reading or writing any miz file
+    /// When to wait for a key press before exiting, when running in a terminal [default: always]
+    #[clap(long, value_enum, value_name = "WHEN", global = true)]
+    pause: Option<PauseArg>,
+
+    /// Seconds to wait for a key press before exiting anyway, 0 to wait forever [default: 30]
+    #[clap(long, value_name = "SECONDS", global = true)]
+    pause_timeout: Option<u64>,
+
+    /// Text printed while waiting for a key press, empty to print nothing
+    #[clap(long, value_name = "TEXT", global = true)]
+    pause_prompt: Option<String>,
+
+    /// Read the configuration from this file instead of repack.toml
+    ///
+    /// Can be given multiple times, with values in later files overriding earlier ones.
+    /// The repack.toml inside the miz and a `<mission_name>.repack.toml` next to it are
+    /// always applied on top of these.
+    #[clap(long = "config", short, value_name = "PATH", global = true)]
+    config: Vec<PathBuf>,
+
+    /// Only print warnings and errors
+    #[clap(long, short, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print more details, repeat for even more (`-vv`)
+    #[clap(long, short, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Append timestamped log entries to this file
+    #[clap(long, value_name = "PATH", global = true)]
+    log_file: Option<PathBuf>,
+}
+
+/// Command line values of `PauseMode`, same as in `[misc]`
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum PauseArg {
+    Always,
+    OnError,
+    Never,
+}
+
+impl From<PauseArg> for PauseMode {
+    fn from(pause: PauseArg) -> Self {
+        match pause {
+            PauseArg::Always => PauseMode::Always,
+            PauseArg::OnError => PauseMode::OnError,
+            PauseArg::Never => PauseMode::Never,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate a new miz for every preset (default when only paths are given)
+    Repack(RepackArgs),
+
+    /// Check the configuration for problems, without reading or writing any miz file
+    Validate,
+
+    /// Print the current environment settings of a miz
+    Inspect(InspectArgs),
+
+    /// Compare the archive entries and mission values of two miz files
+    Diff(DiffArgs),
+
+    /// Extract files from a miz archive
+    Extract(ExtractArgs),
+
+    /// Print the values every preset would generate, without reading or writing any miz file
+    Preview(PreviewArgs),
+}
+
+#[derive(Args, Debug)]
+struct RepackArgs {
+    /// Optional: relative or absolute paths of the DCS mission files to repack
+    ///
+    /// Glob patterns such as `missions/*.miz` are expanded. If no path is given, the last
+    /// repacked miz, as recorded in `repacker_recent.txt`, is used instead
+    #[clap(value_name = "MIZ_PATH")]
+    miz_paths: Vec<String>,
+
+    /// Apply every preset in memory and report the changes, without writing any file
     #[clap(long, short)]
     dry_run: bool,
+
+    /// Seed for the random weather generation, to reproduce the result of a previous run
+    #[clap(long)]
+    seed: Option<u64>,
+
+    /// Ignore the repack.toml stored inside the miz, if there is one
+    #[clap(long)]
+    no_embedded_config: bool,
+
+    /// Keep running and repack every miz in this directory whenever it is saved
+    #[clap(long, value_name = "DIR", conflicts_with_all = ["miz_paths", "dry_run", "report"])]
+    watch: Option<PathBuf>,
+
+    /// Write a JSON report of the generated missions, sampled values, warnings and errors
+    #[clap(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct InspectArgs {
+    /// Relative or absolute path of the miz to inspect
+    miz_path: PathBuf,
+
+    /// Print the settings as JSON instead of a table
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Args, Debug)]
+struct DiffArgs {
+    /// Relative or absolute path of the original miz
+    old_path: PathBuf,
+
+    /// Relative or absolute path of the miz to compare it to, ie. a generated mission
+    new_path: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct ExtractArgs {
+    /// Relative or absolute path of the miz to extract
+    miz_path: PathBuf,
+
+    /// Only extract the entries matching these names or glob patterns, ie. `mission` or `l10n/**`
+    #[clap(value_name = "ENTRY")]
+    entries: Vec<String>,
+
+    /// Directory to extract into, defaults to a folder named after the miz next to it
+    #[clap(long, short, value_name = "DIR")]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct PreviewArgs {
+    /// Seed for the random weather generation, to reproduce the result of a previous run
+    #[clap(long)]
+    seed: Option<u64>,
+}
+
+/// Canonicalizes the `--config` paths, which are relative to the original working directory
+fn canonicalize_config_paths(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    paths
+        .iter()
+        .map(|path| {
+            path.canonicalize().with_context(|| {
+                Failure::new(
+                    FailureKind::MissingInput,
+                    format!("Cannot open config file {}", path.display()),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Runs the command, storing the `[misc]` options of the last configuration read in `misc`,
+/// so that the pause before exiting follows the configuration that was actually used
+fn run(cli: &Cli, explicit_config_paths: &[PathBuf], misc: &mut Option<MiscOptions>) -> Result<()> {
+    match &cli.command {
+        None => repack(&cli.repack, explicit_config_paths, misc),
+        Some(Command::Repack(args)) => repack(args, explicit_config_paths, misc),
+        Some(Command::Validate) => validate(explicit_config_paths, misc),
+        Some(Command::Inspect(args)) => inspect(&args.miz_path, args.json),
+        Some(Command::Diff(args)) => diff(&args.old_path, &args.new_path),
+        Some(Command::Extract(args)) => {
+            extract(&args.miz_path, args.output.as_deref(), &args.entries)
+        }
+        Some(Command::Preview(args)) => preview(args.seed, explicit_config_paths, misc),
+    }
+}
+
+/// Prints the environment settings of a miz, either as a table or as JSON
+fn inspect(miz_path: &Path, json: bool) -> Result<()> {
+    let environment = inspect_miz(miz_path)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&environment)?);
+    } else {
+        println!("{}:\n{environment}\n", miz_path.display());
+    }
+    Ok(())
 }
 
-fn run(miz_path: &Option<String>, dry_run: bool) -> Result<()> {
-    let config = read_config().context("Failed to read configuration from repack.toml")?;
+/// Prints the archive entries and mission values that differ between two miz files
+fn diff(old_path: &Path, new_path: &Path) -> Result<()> {
+    let diff = diff_miz(old_path, new_path)?;
 
-    if dry_run {
-        return repack_miz("dry run", config, true);
+    info!(
+        "Comparing {} to {}...",
+        old_path.display(),
+        new_path.display()
+    );
+    info!("-> Archive entries:");
+    for change in &diff.entries {
+        info!("{change}");
     }
+    if diff.entries.is_empty() {
+        info!("   No changes");
+    }
+
+    info!("-> Mission values:");
+    for change in &diff.mission {
+        info!("{change}");
+    }
+    if diff.mission.is_empty() {
+        info!("   No changes");
+    }
+    info!("");
+    Ok(())
+}
+
+fn extract(miz_path: &Path, output_dir: Option<&Path>, entries: &[String]) -> Result<()> {
+    let extracted = extract_miz(miz_path, output_dir, entries)?;
+
+    info!(
+        "Extracted {} into {}...",
+        miz_path.display(),
+        extracted.output_dir.display()
+    );
+    for name in &extracted.entries {
+        info!("   Extracted {name}");
+    }
+    info!("All done!\n");
+    Ok(())
+}
+
+fn validate(explicit_config_paths: &[PathBuf], misc: &mut Option<MiscOptions>) -> Result<()> {
+    let config_sources = config_sources(explicit_config_paths, None, None);
+    let config = read_config(&config_sources)
+        .with_context(|| Failure::new(FailureKind::Config, "Failed to read configuration"))?;
+    *misc = Some(config.misc.clone());
+    let registry = EditorRegistry::default();
+    let editors = registry.ordered(config.misc.editors.as_deref())?;
+
+    info!(
+        "Configuration is valid: {} preset(s), {} weather preset(s)",
+        config.preset.len(),
+        config.weather.len()
+    );
+    info!("Mission editors, in order, and the settings they read:");
+    for editor in editors {
+        info!(
+            "{}",
+            Change::new(editor.name(), editor.config_keys().join(", "))
+        );
+    }
+    info!("");
+    Ok(())
+}
+
+fn preview(
+    seed: Option<u64>,
+    explicit_config_paths: &[PathBuf],
+    misc: &mut Option<MiscOptions>,
+) -> Result<()> {
+    let config_sources = config_sources(explicit_config_paths, None, None);
+    let config = read_config(&config_sources)
+        .with_context(|| Failure::new(FailureKind::Config, "Failed to read configuration"))?;
+    *misc = Some(config.misc.clone());
+
+    let seed = seed.unwrap_or_else(|| thread_rng().gen());
+    let mut report = MissionReport::new(Path::new("dry run"));
+    repack_miz("dry run", config, seed, RunMode::Preview, &mut report)
+}
+
+fn repack(
+    args: &RepackArgs,
+    explicit_config_paths: &[PathBuf],
+    misc: &mut Option<MiscOptions>,
+) -> Result<()> {
+    if let Some(watch_dir) = &args.watch {
+        return watch(watch_dir, args, explicit_config_paths, misc);
+    }
+
+    // Processing a miz changes the working directory, so resolve the report path first
+    let report_path = args.report.as_deref().map(absolute).transpose()?;
+    let mut report = RunReport {
+        dry_run: args.dry_run,
+        ..Default::default()
+    };
+    let result = repack_all(args, explicit_config_paths, misc, &mut report);
+
+    if let Some(report_path) = report_path {
+        if let Err(err) = &result {
+            report.error = Some(format!("{err:#}"));
+        }
+        report.write(&report_path).with_context(|| {
+            Failure::new(FailureKind::WriteFailed, "Failed to write the report")
+        })?;
+        info!("Report written to {}\n", report_path.display());
+    }
+    result
+}
 
-    // Open either the argument or the most recently opened miz
-    let miz_path = miz_path
-        .as_ref()
-        .map(|miz_path| Ok(miz_path.clone()))
-        .unwrap_or_else(|| -> Result<String> {
-            let recent_path = recent_file_path()?;
-            if !recent_path.is_file() {
-                return miz_not_found_error();
+fn repack_all(
+    args: &RepackArgs,
+    explicit_config_paths: &[PathBuf],
+    misc: &mut Option<MiscOptions>,
+    report: &mut RunReport,
+) -> Result<()> {
+    // Open either the arguments or the most recently opened miz
+    let miz_paths = if args.miz_paths.is_empty() {
+        let recent_path = recent_file_path()?;
+        if !recent_path.is_file() {
+            return miz_not_found_error();
+        }
+        match BufReader::new(File::open(recent_path)?).lines().next() {
+            Some(Ok(recent)) => {
+                info!("Trying most recently opened .miz: {recent}");
+                vec![PathBuf::from(recent)]
             }
-            match BufReader::new(File::open(recent_path)?).lines().next() {
-                Some(Ok(recent)) => {
-                    println!("Trying most recently opened .miz: {recent}");
-                    Ok(recent)
-                }
-                _ => miz_not_found_error(),
+            _ => return miz_not_found_error(),
+        }
+    } else {
+        expand_miz_paths(&args.miz_paths)?
+    };
+
+    // Every miz is canonicalized up front, as processing one changes the working directory.
+    // A missing miz only fails that miz, the others are still processed.
+    let miz_count = miz_paths.len();
+    let miz_paths: Vec<_> = miz_paths
+        .into_iter()
+        .map(|miz_path| match miz_path.canonicalize() {
+            Ok(canonical_path) => (canonical_path, None),
+            Err(err) => (miz_path, Some(err)),
+        })
+        .collect();
+
+    let mut results = Vec::new();
+    let mut generated_files: HashSet<PathBuf> = HashSet::new();
+    for (miz_path, open_error) in miz_paths {
+        // Patterns such as `*.miz` also match the missions generated by this run
+        if generated_files.contains(&miz_path) {
+            info!(
+                "Skipping {}, it was generated by this run\n",
+                miz_path.display()
+            );
+            continue;
+        }
+
+        let mut mission_report = MissionReport::new(&miz_path);
+        let result = match open_error {
+            None => process_miz(
+                &miz_path,
+                args,
+                explicit_config_paths,
+                misc,
+                &mut mission_report,
+            ),
+            Some(err) => Err(anyhow::Error::new(err).context(Failure::new(
+                FailureKind::MissingInput,
+                format!("Cannot open {}", miz_path.display()),
+            ))),
+        };
+        generated_files.extend(
+            mission_report
+                .presets
+                .iter()
+                .filter(|preset| preset.written)
+                .map(|preset| preset.output_path.clone()),
+        );
+        if let Err(err) = &result {
+            mission_report.error = Some(format!("{err:#}"));
+            // The error of a single miz is printed by main
+            if miz_count > 1 {
+                error!("{err:?}\n");
             }
-        })?;
+        }
+        report.missions.push(mission_report);
+        results.push((miz_path, result));
+    }
+
+    if results.len() == 1 {
+        return results.pop().unwrap().1;
+    }
+
+    info!("Summary:");
+    for (miz_path, result) in &results {
+        match result {
+            Ok(_) => info!("   OK      {}", miz_path.display()),
+            Err(err) => info!("   FAILED  {}: {err}", miz_path.display()),
+        }
+    }
+    info!("");
+
+    let errors: Vec<_> = results
+        .iter()
+        .filter_map(|(_, result)| result.as_ref().err())
+        .collect();
+    if let Some(first_error) = errors.first() {
+        let message = format!(
+            "Failed to process {} of {} missions",
+            errors.len(),
+            results.len()
+        );
+        // Exit with the code of the first failure, if it has one
+        return Err(match FailureKind::of(first_error) {
+            Some(kind) => Failure::new(kind, message).into(),
+            None => anyhow!(message),
+        });
+    }
+    Ok(())
+}
+
+/// Expands glob patterns in the given miz paths, ie. `missions/*.miz`
+fn expand_miz_paths(patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        // Plain paths are kept as-is, so that a missing file is reported by name. Existing
+        // files are never treated as patterns, since `[` is common in mission names
+        if !pattern.contains(['*', '?', '[']) || Path::new(pattern).exists() {
+            paths.push(PathBuf::from(pattern));
+            continue;
+        }
+
+        let matches = glob::glob(pattern)
+            .with_context(|| format!("Invalid pattern: {pattern}"))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            return Err(Failure::new(
+                FailureKind::MissingInput,
+                format!("No files match {pattern}"),
+            )
+            .into());
+        }
+        paths.extend(matches);
+    }
+    Ok(paths)
+}
+
+/// Repacks a single miz, recording the absolute paths of the generated missions in the report
+fn process_miz(
+    canonical_path: &Path,
+    args: &RepackArgs,
+    explicit_config_paths: &[PathBuf],
+    misc: &mut Option<MiscOptions>,
+    report: &mut MissionReport,
+) -> Result<()> {
+    let display_path = canonical_path.display();
 
     // Switch to the miz directory
     set_current_dir(
-        Path::new(&miz_path)
-            .canonicalize()
-            .with_context(|| format!("Cannot open {miz_path}"))?
+        canonical_path
             .parent()
-            .ok_or_else(|| anyhow!("Cannot find parent folder of {miz_path}"))?,
+            .ok_or_else(|| anyhow!("Cannot find parent folder of {display_path}"))?,
     )?;
 
-    repack_miz(&miz_path, config, false).with_context(|| format!("Failed to process {miz_path}"))
+    // From here on, the miz is referred to relative to its own directory
+    let miz_path = canonical_path
+        .file_name()
+        .and_then(|file_name| file_name.to_str())
+        .ok_or_else(|| anyhow!("Cannot process non UTF-8 path: {display_path}"))?
+        .to_owned();
+
+    let embedded_config = if args.no_embedded_config {
+        None
+    } else {
+        read_embedded_config(Path::new(&miz_path)).with_context(|| {
+            Failure::new(
+                FailureKind::CorruptArchive,
+                format!("Cannot open {miz_path}"),
+            )
+        })?
+    };
+
+    let config_sources = config_sources(
+        explicit_config_paths,
+        Some(Path::new(&miz_path)),
+        embedded_config,
+    );
+    for config_source in &config_sources {
+        info!("Using configuration from {config_source}");
+    }
+    let config = read_config(&config_sources).with_context(|| {
+        Failure::new(
+            FailureKind::Config,
+            format!("Failed to read configuration for {miz_path}"),
+        )
+    })?;
+    *misc = Some(config.misc.clone());
+
+    let seed = args.seed.unwrap_or_else(|| thread_rng().gen());
+    let mode = if args.dry_run {
+        RunMode::DryRun
+    } else {
+        RunMode::Write
+    };
+    report.seed = Some(seed);
+    let result = repack_miz(&miz_path, config, seed, mode, report)
+        .with_context(|| format!("Failed to process {miz_path}"));
+
+    let miz_dir = canonical_path.parent().unwrap();
+    for preset in &mut report.presets {
+        preset.output_path = miz_dir.join(&preset.output_path);
+    }
+    result
+}
+
+/// Returns the modification time and size of every miz in the directory, excluding backups
+fn scan_miz_files(dir: &Path) -> Result<HashMap<PathBuf, (SystemTime, u64)>> {
+    let mut files = HashMap::new();
+    let entries = WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != BACKUP_DIR);
+    for entry in entries {
+        let entry = entry?;
+        let is_miz = entry
+            .path()
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("miz"));
+        if entry.file_type().is_file() && is_miz {
+            let metadata = entry.metadata()?;
+            files.insert(
+                entry.path().to_owned(),
+                (metadata.modified()?, metadata.len()),
+            );
+        }
+    }
+    Ok(files)
+}
+
+/// Keeps running, repacking every miz in the directory when it is saved
+fn watch(
+    dir: &Path,
+    args: &RepackArgs,
+    explicit_config_paths: &[PathBuf],
+    misc: &mut Option<MiscOptions>,
+) -> Result<()> {
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+    // Wait for files to stop changing, so that partially written files are not read
+    const DEBOUNCE: Duration = Duration::from_secs(3);
+
+    let dir = dir.canonicalize().with_context(|| {
+        Failure::new(
+            FailureKind::MissingInput,
+            format!("Cannot open {}", dir.display()),
+        )
+    })?;
+    info!("Watching {} for saved missions...\n", dir.display());
+
+    let mut known_files = scan_miz_files(&dir)?;
+    let mut changed_files: HashMap<PathBuf, Instant> = HashMap::new();
+    let mut generated_files: HashSet<PathBuf> = HashSet::new();
+
+    loop {
+        sleep(POLL_INTERVAL);
+
+        // Files can disappear mid-scan, ie. when the mission editor saves, so try again later
+        let files = match scan_miz_files(&dir) {
+            Ok(files) => files,
+            Err(err) => {
+                warn!("Cannot scan {}: {err:#}", dir.display());
+                continue;
+            }
+        };
+        for (path, state) in &files {
+            if !generated_files.contains(path) && known_files.get(path) != Some(state) {
+                changed_files.insert(path.clone(), Instant::now());
+            }
+        }
+        known_files = files;
+
+        let ready: Vec<_> = changed_files
+            .iter()
+            .filter(|(_, changed_at)| changed_at.elapsed() >= DEBOUNCE)
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        for path in ready {
+            changed_files.remove(&path);
+            info!("Detected change in {}", path.display());
+            let mut report = MissionReport::new(&path);
+            let result = process_miz(&path, args, explicit_config_paths, misc, &mut report);
+            generated_files.extend(
+                report
+                    .presets
+                    .into_iter()
+                    .filter(|preset| preset.written)
+                    .map(|preset| preset.output_path),
+            );
+            if let Err(err) = result {
+                error!("{err:?}\n");
+            }
+        }
+    }
+}
+
+/// How to wait for the user before exiting, from the command line or the `[misc]` options
+struct PauseOptions {
+    mode: PauseMode,
+    timeout: u64,
+    prompt: Option<String>,
+}
+
+/// Returns the pause options, with the command line taking precedence over the `[misc]`
+/// options of the configuration the run used, if it read one
+fn pause_options(cli: Option<&Cli>, misc: Option<&MiscOptions>) -> PauseOptions {
+    let mode = match cli {
+        Some(cli) if cli.batch => Some(PauseMode::Never),
+        Some(cli) => cli.pause.map(PauseMode::from),
+        None => None,
+    };
+    PauseOptions {
+        mode: mode
+            .or_else(|| misc.and_then(|misc| misc.pause))
+            .unwrap_or_default(),
+        timeout: cli
+            .and_then(|cli| cli.pause_timeout)
+            .or_else(|| misc.and_then(|misc| misc.pause_timeout))
+            .unwrap_or(30),
+        prompt: cli
+            .and_then(|cli| cli.pause_prompt.clone())
+            .or_else(|| misc.and_then(|misc| misc.pause_prompt.clone())),
+    }
 }
 
-fn pause_and_exit(code: i32, batch: bool) -> ! {
+/// Returns the pause options of a command line that cannot be parsed, so that usage errors
+/// still respect `--batch` and `--pause`
+fn usage_error_pause_options(args: &[OsString]) -> PauseOptions {
+    const PAUSE_ARGS: &[&str] = &["--pause", "--pause-timeout", "--pause-prompt"];
+
+    let mut batch = false;
+    let mut pause_args = vec![OsString::from("repacker")];
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let Some(text) = arg.to_str() else {
+            continue;
+        };
+        if text == "--" {
+            break;
+        }
+        let name = text.split_once('=').map_or(text, |(name, _)| name);
+        if name == "--batch" {
+            batch = true;
+        } else if PAUSE_ARGS.contains(&name) {
+            pause_args.push(arg.clone());
+            if name == text {
+                pause_args.extend(args.next().cloned());
+            }
+        } else if !text.starts_with("--") && text.starts_with('-') {
+            // Short flags can be combined, ie. `-qb`, up to `-c` and its value
+            batch |= text[1..]
+                .chars()
+                .take_while(|&flag| flag != 'c')
+                .any(|flag| flag == 'b');
+        }
+    }
+
+    // An invalid pause argument falls back to the defaults, but `--batch` still applies
+    let cli = Cli::try_parse_from(&pause_args).ok();
+    let mut options = pause_options(cli.as_ref(), None);
+    if batch {
+        options.mode = PauseMode::Never;
+    }
+    options
+}
+
+fn pause_and_exit(code: i32, options: &PauseOptions) -> ! {
+    let pause = match options.mode {
+        PauseMode::Always => true,
+        PauseMode::OnError => code != 0,
+        PauseMode::Never => false,
+    };
     // Exit if not running in a terminal or in non-interactive mode
-    if !stdout().is_tty() || batch {
+    if !stdout().is_tty() || !pause {
         exit(code);
     }
     // Auto-exit if the user doesn't respnd
-    spawn(move || {
-        sleep(Duration::from_secs(30));
-        eprintln!("Timed out waiting for response");
-        exit(code);
-    });
+    let timeout = options.timeout;
+    if timeout > 0 {
+        spawn(move || {
+            sleep(Duration::from_secs(timeout));
+            eprintln!("Timed out waiting for response");
+            exit(code);
+        });
+    }
     // Wait for user response...
-    eprintln!("Press any key or wait 30 seconds to continue...");
+    let prompt = match &options.prompt {
+        Some(prompt) => prompt.clone(),
+        None if timeout > 0 => {
+            format!("Press any key or wait {timeout} seconds to continue...")
+        }
+        None => "Press any key to continue...".to_owned(),
+    };
+    if !prompt.is_empty() {
+        eprintln!("{prompt}");
+    }
     terminal::enable_raw_mode().unwrap();
     loop {
         if let Event::Key(_) = event::read().unwrap() {
@@ -269,20 +982,209 @@ fn pause_and_exit(code: i32, batch: bool) -> ! {
     }
 }
 
+/// Parses the command line, rejecting the arguments of the default repack command when a
+/// subcommand is given, since the subcommand would ignore them
+fn parse_cli<I, T>(args: I) -> Result<Cli, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let matches = Cli::command().try_get_matches_from(args)?;
+    let cli = Cli::from_arg_matches(&matches)?;
+    if let Some((name, _)) = matches.subcommand() {
+        let mut repack_command = RepackArgs::augment_args(clap::Command::new("repack"));
+        repack_command.build();
+        let repack_args: Vec<_> = repack_command
+            .get_arguments()
+            .filter(|arg| {
+                matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+            })
+            .map(|arg| format!("'{arg}'"))
+            .collect();
+        if !repack_args.is_empty() {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "{} can only be used with the repack command, not with '{name}'",
+                    repack_args.join(", ")
+                ),
+            ));
+        }
+    }
+    Ok(cli)
+}
+
 fn main() {
-    match Args::try_parse() {
-        Ok(args) => match run(&args.miz_path, args.dry_run) {
-            Ok(_) => pause_and_exit(0, args.batch),
-            Err(err) => {
+    let args: Vec<_> = args_os().collect();
+    match parse_cli(&args) {
+        Ok(cli) => {
+            let console_level = logging::console_level(cli.quiet, cli.verbose);
+            if let Err(err) = logging::init(console_level, cli.log_file.as_deref()) {
                 eprintln!("{err:?}\n");
-                pause_and_exit(1, args.batch);
+                let code = FailureKind::WriteFailed.exit_code();
+                pause_and_exit(code, &pause_options(Some(&cli), None));
             }
-        },
+
+            let mut misc = None;
+            let result = canonicalize_config_paths(&cli.config)
+                .and_then(|explicit_config_paths| run(&cli, &explicit_config_paths, &mut misc));
+            let pause = pause_options(Some(&cli), misc.as_ref());
+            match result {
+                Ok(_) => pause_and_exit(0, &pause),
+                Err(err) => {
+                    error!("{err:?}\n");
+                    let code = FailureKind::of(&err).map_or(1, FailureKind::exit_code);
+                    pause_and_exit(code, &pause);
+                }
+            }
+        }
         Err(err) if err.use_stderr() => {
             err.print().unwrap();
             eprintln!();
-            pause_and_exit(2, false);
+            pause_and_exit(2, &usage_error_pause_options(&args));
         }
         Err(err) => err.exit(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn expand(dir: &Path, patterns: &[&str]) -> Result<Vec<PathBuf>> {
+        let patterns: Vec<_> = patterns
+            .iter()
+            .map(|pattern| dir.join(pattern).to_str().unwrap().to_owned())
+            .collect();
+        expand_miz_paths(&patterns)
+    }
+
+    #[test]
+    fn expands_glob_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["b.miz", "a.miz", "notes.txt"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+
+        let paths = expand(dir.path(), &["*.miz"]).unwrap();
+        assert_eq!(paths, [dir.path().join("a.miz"), dir.path().join("b.miz")]);
+    }
+
+    #[test]
+    fn keeps_existing_files_with_pattern_characters() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Op [v2].miz"), "").unwrap();
+
+        let paths = expand(dir.path(), &["Op [v2].miz"]).unwrap();
+        assert_eq!(paths, [dir.path().join("Op [v2].miz")]);
+    }
+
+    #[test]
+    fn keeps_missing_plain_paths() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let paths = expand(dir.path(), &["missing.miz"]).unwrap();
+        assert_eq!(paths, [dir.path().join("missing.miz")]);
+    }
+
+    #[test]
+    fn fails_when_a_pattern_matches_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let err = expand(dir.path(), &["*.miz"]).unwrap_err();
+        assert_eq!(FailureKind::of(&err), Some(FailureKind::MissingInput));
+    }
+
+    #[test]
+    fn keeps_repacking_after_a_missing_miz() {
+        let dir = tempfile::tempdir().unwrap();
+        let miz_path = dir.path().join("test.miz");
+        let mut zip = zip::ZipWriter::new(File::create(&miz_path).unwrap());
+        zip.start_file("mission", Default::default()).unwrap();
+        zip.write_all(b"mission = \n{\n    [\"start_time\"] = 28800,\n} -- end of mission\n")
+            .unwrap();
+        zip.finish().unwrap();
+        let config_path = dir.path().join("repack.toml");
+        fs::write(
+            &config_path,
+            "[misc]\n[weather]\n[preset.morning]\ntime = \"06:00\"\n",
+        )
+        .unwrap();
+
+        let args = RepackArgs {
+            miz_paths: ["missing.miz", "test.miz"]
+                .map(|name| dir.path().join(name).to_str().unwrap().to_owned())
+                .to_vec(),
+            dry_run: false,
+            seed: Some(1),
+            no_embedded_config: true,
+            watch: None,
+            report: None,
+        };
+        let mut report = RunReport::default();
+        let err = repack_all(&args, &[config_path], &mut None, &mut report).unwrap_err();
+
+        assert_eq!(FailureKind::of(&err), Some(FailureKind::MissingInput));
+        assert_eq!(err.to_string(), "Failed to process 1 of 2 missions");
+        assert_eq!(report.missions.len(), 2);
+        let error = report.missions[0].error.as_deref().unwrap();
+        assert!(error.starts_with("Cannot open"), "{error}");
+        assert_eq!(report.missions[1].error, None);
+        assert!(dir.path().join("test_morning.miz").is_file());
+    }
+
+    #[test]
+    fn rejects_repack_arguments_with_subcommands() {
+        for args in [
+            &["repacker", "--seed", "3", "preview"][..],
+            &["repacker", "--dry-run", "validate"],
+            &["repacker", "--report", "r.json", "diff", "a.miz", "b.miz"],
+        ] {
+            let err = parse_cli(args).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ArgumentConflict, "{args:?}");
+        }
+
+        for args in [
+            &["repacker", "--batch", "-c", "repack.toml", "validate"][..],
+            &["repacker", "preview", "--seed", "3"],
+            &["repacker", "--seed", "3", "--dry-run", "test.miz"],
+            &["repacker", "repack", "--seed", "3", "test.miz"],
+        ] {
+            assert!(parse_cli(args).is_ok(), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn usage_errors_respect_the_pause_arguments() {
+        let options = |args: &[&str]| {
+            let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+            let options = usage_error_pause_options(&args);
+            (options.mode, options.timeout)
+        };
+
+        assert_eq!(options(&["r", "--bogus"]), (PauseMode::Always, 30));
+        assert_eq!(
+            options(&["r", "--batch", "--bogus"]),
+            (PauseMode::Never, 30)
+        );
+        assert_eq!(options(&["r", "-qb", "--bogus"]), (PauseMode::Never, 30));
+        assert_eq!(
+            options(&["r", "-c", "b.toml", "--bogus"]),
+            (PauseMode::Always, 30)
+        );
+        assert_eq!(
+            options(&["r", "--pause", "never", "--bogus"]),
+            (PauseMode::Never, 30)
+        );
+        assert_eq!(
+            options(&["r", "--pause=on-error", "--pause-timeout", "5", "bogus"]),
+            (PauseMode::OnError, 5)
+        );
+        assert_eq!(
+            options(&["r", "--pause", "nevr", "--batch"]),
+            (PauseMode::Never, 30)
+        );
+        assert_eq!(options(&["r", "--", "--batch"]), (PauseMode::Always, 30));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::report::MissionReport;
 use anyhow::Result;
 use once_cell::sync::Lazy;
 use regex::{Captures, Regex, RegexBuilder};
@@ -11,7 +12,11 @@ const REQUIRED_MODULES_REGEX: &str = concat! {
     r#"(\s+\}, -- end of \["requiredModules"\]\n)"#,
 };
 
-pub fn remove_required_modules(mission: &str, dry_run: bool) -> Result<String> {
+pub fn remove_required_modules(
+    mission: &str,
+    dry_run: bool,
+    report: &mut MissionReport,
+) -> Result<String> {
     static REGEX: Lazy<Regex> = Lazy::new(|| {
         RegexBuilder::new(REQUIRED_MODULES_REGEX)
             .multi_line(true)
@@ -20,8 +25,9 @@ pub fn remove_required_modules(mission: &str, dry_run: bool) -> Result<String> {
     });
 
     if !dry_run && !REGEX.is_match(mission) {
-        println!(
-            "?> The mission does not seem to have a requiredModules table, no need to remove it..."
+        report.warn(
+            "The mission does not seem to have a requiredModules table, no need to remove it..."
+                .to_owned(),
         );
         return Ok(mission.to_owned());
     }
@@ -0,0 +1,348 @@
+use crate::config::MiscOptions;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use log::info;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use std::{
+    fs::{self, create_dir_all},
+    path::{Component, Path, PathBuf},
+};
+
+/// Name of the directory, next to the generated missions, where previous versions are kept
+pub const BACKUP_DIR: &str = "backup";
+
+/// Name of the generated missions when `output_name` is not set, ie. `mission_morning.miz`
+pub const DEFAULT_OUTPUT_NAME: &str = "{stem}_{preset}.{ext}";
+
+const PLACEHOLDERS: &[&str] = &["stem", "ext", "preset", "weather", "seed", "date", "time"];
+
+static PLACEHOLDER_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{(\w*)\}").unwrap());
+
+/// Values available to the `output_name` template of a generated mission
+pub struct OutputVars<'a> {
+    pub preset: &'a str,
+    pub weather: Option<&'a str>,
+    pub seed: u64,
+    pub timestamp: DateTime<Local>,
+}
+
+/// Returns the path of a generated mission, relative to the directory of the source miz
+pub fn output_path(miz_path: &str, misc: &MiscOptions, vars: &OutputVars) -> Result<PathBuf> {
+    let miz_path = Path::new(miz_path);
+    let stem = miz_path.file_stem().and_then(|stem| stem.to_str());
+    let ext = miz_path.extension().and_then(|ext| ext.to_str());
+    let (stem, ext) = match (stem, ext) {
+        (Some(stem), Some(ext)) => (stem, ext),
+        _ => return Err(anyhow!("File extension missing")),
+    };
+
+    check_preset_name("Preset", vars.preset)?;
+    if let Some(weather) = vars.weather {
+        check_preset_name("Weather preset", weather)?;
+    }
+    let template = misc.output_name.as_deref().unwrap_or(DEFAULT_OUTPUT_NAME);
+    check_output_name(template)?;
+    let output_dir = misc.output_dir.as_deref().unwrap_or("");
+    check_output_dir(output_dir)?;
+
+    let file_name = PLACEHOLDER_REGEX.replace_all(template, |cap: &Captures| match &cap[1] {
+        "stem" => stem.to_owned(),
+        "ext" => ext.to_owned(),
+        "preset" => vars.preset.to_owned(),
+        "weather" => vars.weather.unwrap_or("none").to_owned(),
+        "seed" => vars.seed.to_string(),
+        "date" => vars.timestamp.format("%Y-%m-%d").to_string(),
+        "time" => vars.timestamp.format("%H-%M-%S").to_string(),
+        _ => unreachable!(),
+    });
+
+    let path = Path::new(output_dir).join(file_name.as_ref());
+    check_relative_path("The generated miz path", &path.to_string_lossy())?;
+    Ok(path)
+}
+
+/// Checks that an `output_name` template only uses known placeholders, contains `{preset}` so
+/// that the presets do not overwrite each other, and stays inside the output directory
+pub fn check_output_name(template: &str) -> Result<()> {
+    for cap in PLACEHOLDER_REGEX.captures_iter(template) {
+        if !PLACEHOLDERS.contains(&&cap[1]) {
+            return Err(anyhow!(
+                "Unknown placeholder {} in output_name, expected one of: {{{}}}",
+                &cap[0],
+                PLACEHOLDERS.join("}, {")
+            ));
+        }
+    }
+    if !template.contains("{preset}") {
+        return Err(anyhow!(
+            "output_name must contain {{preset}}, otherwise every preset writes to the same file"
+        ));
+    }
+    check_relative_path("output_name", template)
+}
+
+/// Checks that `output_dir` stays inside the directory of the source miz
+pub fn check_output_dir(output_dir: &str) -> Result<()> {
+    check_relative_path("output_dir", output_dir)
+}
+
+/// Checks that a preset or weather preset name can be used in file and folder names, since it
+/// is part of the generated miz name and of the repack folders
+pub fn check_preset_name(kind: &str, name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) || name.contains("..")
+    {
+        return Err(anyhow!(
+            "{kind} '{name}' cannot be used in file names, it must not be empty, start with '.' or contain '/', '\\' or '..'"
+        ));
+    }
+    Ok(())
+}
+
+fn check_relative_path(key: &str, path: &str) -> Result<()> {
+    let is_outside = Path::new(path).components().any(|component| {
+        matches!(
+            component,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_)
+        )
+    });
+    if is_outside {
+        return Err(anyhow!(
+            "{key} must be a relative path without '..' components, found: {path}"
+        ));
+    }
+    Ok(())
+}
+
+/// Copies the existing file at `path` into the backup directory, named after its modification
+/// time, and deletes the oldest backups of that file so that at most `backup_count` are kept
+pub fn backup_previous_output(path: &Path, backup_count: usize) -> Result<()> {
+    if backup_count == 0 || !path.is_file() {
+        return Ok(());
+    }
+
+    let stem = path.file_stem().and_then(|stem| stem.to_str());
+    let ext = path.extension().and_then(|ext| ext.to_str());
+    let (stem, ext) = match (stem, ext) {
+        (Some(stem), Some(ext)) => (stem, ext),
+        _ => return Err(anyhow!("File extension missing")),
+    };
+
+    let backup_dir = path.with_file_name(BACKUP_DIR);
+    create_dir_all(&backup_dir)?;
+
+    let modified: DateTime<Local> = fs::metadata(path)?.modified()?.into();
+    let backup_path = backup_dir.join(format!("{stem}.{}.{ext}", modified.format("%Y%m%d-%H%M%S")));
+
+    // Copy instead of moving, so that the destination is never missing for a running server
+    info!(
+        "   Backing up previous version to {}",
+        backup_path.display()
+    );
+    fs::copy(path, &backup_path)?;
+
+    // Timestamps sort chronologically, so the oldest backups come first
+    let backup_regex = Regex::new(&format!(
+        r"^{}\.\d{{8}}-\d{{6}}\.{}$",
+        regex::escape(stem),
+        regex::escape(ext)
+    ))?;
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(&backup_dir)? {
+        let entry = entry?;
+        if let Some(file_name) = entry.file_name().to_str() {
+            if backup_regex.is_match(file_name) {
+                backups.push(entry.path());
+            }
+        }
+    }
+    backups.sort();
+
+    let excess = backups.len().saturating_sub(backup_count);
+    for old_backup in &backups[..excess] {
+        info!("   Deleting old backup {}", old_backup.display());
+        fs::remove_file(old_backup)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn vars(weather: Option<&'static str>) -> OutputVars<'static> {
+        OutputVars {
+            preset: "morning",
+            weather,
+            seed: 42,
+            timestamp: Local.with_ymd_and_hms(2024, 5, 17, 8, 30, 15).unwrap(),
+        }
+    }
+
+    fn misc(output_dir: Option<&str>, output_name: Option<&str>) -> MiscOptions {
+        let mut misc: MiscOptions = toml::from_str("").unwrap();
+        misc.output_dir = output_dir.map(str::to_owned);
+        misc.output_name = output_name.map(str::to_owned);
+        misc
+    }
+
+    #[test]
+    fn default_output_name() {
+        let path = output_path("test.miz", &misc(None, None), &vars(None)).unwrap();
+        assert_eq!(path, PathBuf::from("test_morning.miz"));
+    }
+
+    #[test]
+    fn replaces_every_placeholder() {
+        let misc = misc(
+            Some("generated"),
+            Some("{stem}-{preset}-{weather}-{seed}-{date}-{time}.{ext}"),
+        );
+        let path = output_path("test.miz", &misc, &vars(Some("rain"))).unwrap();
+        assert_eq!(
+            path,
+            Path::new("generated").join("test-morning-rain-42-2024-05-17-08-30-15.miz")
+        );
+
+        let path = output_path("test.miz", &misc, &vars(None)).unwrap();
+        assert_eq!(
+            path,
+            Path::new("generated").join("test-morning-none-42-2024-05-17-08-30-15.miz")
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders() {
+        let misc = misc(None, Some("{stem}_{preset}_{unknown}.{ext}"));
+        assert!(output_path("test.miz", &misc, &vars(None)).is_err());
+    }
+
+    #[test]
+    fn rejects_templates_without_preset() {
+        let misc = misc(None, Some("{stem}.{ext}"));
+        let err = output_path("test.miz", &misc, &vars(None)).unwrap_err();
+        assert!(err.to_string().contains("{preset}"), "{err}");
+    }
+
+    #[test]
+    fn rejects_preset_names_outside_of_the_output_directory() {
+        for (preset, weather) in [
+            ("../../x", None),
+            ("..", None),
+            ("sub/dir", None),
+            ("sub\\dir", None),
+            (".hidden", None),
+            ("", None),
+            ("morning", Some("../rain")),
+        ] {
+            let vars = OutputVars {
+                preset,
+                weather,
+                ..vars(None)
+            };
+            let misc = misc(None, Some("{weather}_{preset}.{ext}"));
+            assert!(
+                output_path("test.miz", &misc, &vars).is_err(),
+                "{preset} {weather:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_paths_outside_of_the_miz_directory() {
+        for (output_dir, output_name) in [
+            (Some(".."), None),
+            (Some("out/../.."), None),
+            (Some("/tmp"), None),
+            (None, Some("../{stem}_{preset}.{ext}")),
+            (None, Some("/tmp/{stem}_{preset}.{ext}")),
+        ] {
+            let misc = misc(output_dir, output_name);
+            assert!(
+                output_path("test.miz", &misc, &vars(None)).is_err(),
+                "{output_dir:?} {output_name:?}"
+            );
+        }
+    }
+
+    fn write_with_mtime(path: &Path, contents: &str, modified: DateTime<Local>) {
+        fs::write(path, contents).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified.into())
+            .unwrap();
+    }
+
+    fn backups(dir: &Path) -> Vec<(String, String)> {
+        let mut backups: Vec<_> = fs::read_dir(dir.join(BACKUP_DIR))
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+                (name, fs::read_to_string(path).unwrap())
+            })
+            .collect();
+        backups.sort();
+        backups
+    }
+
+    #[test]
+    fn keeps_the_last_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test_morning.miz");
+        fs::create_dir_all(dir.path().join(BACKUP_DIR)).unwrap();
+        let other_backup = dir
+            .path()
+            .join(BACKUP_DIR)
+            .join("test_evening.20200101-000000.miz");
+        fs::write(&other_backup, "evening").unwrap();
+
+        for day in 1..=4 {
+            let modified = Local.with_ymd_and_hms(2024, 5, day, 8, 30, 0).unwrap();
+            write_with_mtime(&path, &format!("version {day}"), modified);
+            backup_previous_output(&path, 2).unwrap();
+        }
+
+        assert_eq!(
+            backups(dir.path()),
+            [
+                (
+                    "test_evening.20200101-000000.miz".to_owned(),
+                    "evening".to_owned()
+                ),
+                (
+                    "test_morning.20240503-083000.miz".to_owned(),
+                    "version 3".to_owned()
+                ),
+                (
+                    "test_morning.20240504-083000.miz".to_owned(),
+                    "version 4".to_owned()
+                ),
+            ]
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "version 4");
+    }
+
+    #[test]
+    fn no_backups_without_backup_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test_morning.miz");
+        fs::write(&path, "version 1").unwrap();
+
+        backup_previous_output(&path, 0).unwrap();
+        assert!(!dir.path().join(BACKUP_DIR).exists());
+    }
+
+    #[test]
+    fn no_backups_without_previous_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test_morning.miz");
+
+        backup_previous_output(&path, 3).unwrap();
+        assert!(!dir.path().join(BACKUP_DIR).exists());
+    }
+}
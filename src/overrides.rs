@@ -0,0 +1,134 @@
+use crate::{
+    editor::{Change, Edit, EditContext, MissionEditor},
+    failure::{Failure, FailureKind},
+    lua::{parse_lua_assignment, to_lua_assignment, LuaKey, LuaValue},
+};
+use anyhow::{Context, Result};
+use toml::{value::Table, Value};
+
+/// Sets values of the `options` and `warehouses` files from the tables of the same name in
+/// the preset, ie. `labels = 0` in `[preset.night.options.difficulty]`
+pub struct OverrideEditor;
+
+impl MissionEditor for OverrideEditor {
+    fn name(&self) -> &'static str {
+        "overrides"
+    }
+
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["options", "warehouses"]
+    }
+
+    fn apply(&self, mission: &str, context: &mut EditContext) -> Result<Edit> {
+        let overrides = [
+            ("options", "Options", &context.preset.options),
+            ("warehouses", "Warehouses", &context.preset.warehouses),
+        ];
+
+        let mut changes = Vec::new();
+        for (file, label, overrides) in overrides {
+            let Some(overrides) = overrides else {
+                continue;
+            };
+
+            let mut values = Vec::new();
+            if context.dry_run {
+                // Without the file, only list the values that would be set
+                override_table(&mut LuaValue::Table(Vec::new()), overrides, "", &mut values);
+            } else {
+                let source = context.files.get(file).ok_or_else(|| {
+                    Failure::new(
+                        FailureKind::MissingMissionKey,
+                        format!("Cannot override {file} values, the miz has no {file} file"),
+                    )
+                })?;
+                let (name, mut value) = parse_lua_assignment(source)
+                    .with_context(|| format!("Cannot read {file} file"))?;
+                override_table(&mut value, overrides, "", &mut values);
+                context
+                    .files
+                    .insert(file.to_owned(), to_lua_assignment(&name, &value));
+            }
+
+            for (path, value) in values {
+                changes.push(Change::new(label, format!("{path} = {value}")));
+            }
+        }
+
+        Ok(Edit {
+            mission: mission.to_owned(),
+            changes,
+        })
+    }
+}
+
+/// Sets the values of a TOML table in a Lua table, merging nested tables key by key and
+/// adding any missing entries
+fn override_table(
+    target: &mut LuaValue,
+    overrides: &Table,
+    path: &str,
+    values: &mut Vec<(String, LuaValue)>,
+) {
+    if !matches!(target, LuaValue::Table(_)) {
+        *target = LuaValue::Table(Vec::new());
+    }
+    let LuaValue::Table(entries) = target else {
+        unreachable!()
+    };
+
+    for (key, value) in overrides {
+        let key_path = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{path}.{key}")
+        };
+
+        // Numeric keys match both `[1]` and `["1"]`, same as in `LuaValue::get_path`
+        let position = entries
+            .iter()
+            .position(|(entry_key, _)| entry_key.to_string() == *key);
+        let entry = match position {
+            Some(position) => &mut entries[position].1,
+            None => {
+                let lua_key = key
+                    .parse()
+                    .map(LuaKey::Index)
+                    .unwrap_or_else(|_| LuaKey::Name(key.clone()));
+                entries.push((lua_key, LuaValue::Nil));
+                &mut entries.last_mut().unwrap().1
+            }
+        };
+
+        match value {
+            Value::Table(table) => override_table(entry, table, &key_path, values),
+            value => {
+                *entry = toml_to_lua(value);
+                values.push((key_path, entry.clone()));
+            }
+        }
+    }
+}
+
+fn toml_to_lua(value: &Value) -> LuaValue {
+    match value {
+        Value::String(value) => LuaValue::String(value.clone()),
+        Value::Integer(value) => LuaValue::Integer(*value),
+        Value::Float(value) => LuaValue::Number(*value),
+        Value::Boolean(value) => LuaValue::Bool(*value),
+        Value::Datetime(value) => LuaValue::String(value.to_string()),
+        Value::Array(values) => LuaValue::Table(
+            values
+                .iter()
+                .enumerate()
+                .map(|(index, value)| (LuaKey::Index(index as i64 + 1), toml_to_lua(value)))
+                .collect(),
+        ),
+        Value::Table(table) => LuaValue::Table(
+            table
+                .iter()
+                .map(|(key, value)| (LuaKey::Name(key.clone()), toml_to_lua(value)))
+                .collect(),
+        ),
+    }
+}
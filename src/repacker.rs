@@ -0,0 +1,616 @@
+use crate::{
+    config::Config,
+    editor::{EditContext, EditorRegistry, MissionEditor},
+    failure::{Failure, FailureKind},
+    misc::remove_required_modules,
+    output::check_preset_name,
+    report::MissionReport,
+    template::{render_template, TemplateVars, TEMPLATE_EXTENSION},
+};
+use anyhow::{anyhow, Context, Result};
+use glob::{MatchOptions, Pattern};
+use log::{info, trace};
+use once_cell::unsync::OnceCell;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io::{self, Cursor, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+use zip::{result::ZipError, write::FileOptions, ZipArchive, ZipWriter};
+
+/// Lua files of the miz besides `mission` that the presets can modify
+pub const LUA_FILES: [&str; 2] = ["options", "warehouses"];
+
+/// Folder of the repack directory with the files added to the miz of every preset
+pub const COMMON_DIR: &str = "_common";
+/// Folder of the repack directory with a folder of files for each weather preset
+pub const WEATHER_DIR: &str = "weather";
+
+/// A mission file generated from one preset
+#[derive(Debug, Clone)]
+pub struct GeneratedMission {
+    pub preset: String,
+    /// The chosen weather preset, if the preset has any
+    pub weather: Option<String>,
+    /// Contents of the generated `mission` file
+    pub mission: String,
+    /// Contents of the generated `options` and `warehouses` files, by entry name
+    pub files: BTreeMap<String, String>,
+}
+
+/// A miz archive generated from one preset
+#[derive(Debug, Clone)]
+pub struct GeneratedMiz {
+    pub mission: GeneratedMission,
+    /// Entries of the original archive left out by the `remove` patterns
+    pub removed: Vec<String>,
+    pub data: Vec<u8>,
+}
+
+/// Generates missions from the presets of a configuration
+///
+/// The same seed always generates the same missions, as long as the presets are generated
+/// in the order of `preset_names`.
+pub struct Repacker {
+    config: Config,
+    seed: u64,
+    rng: StdRng,
+    repack_dir: PathBuf,
+    editors: EditorRegistry,
+}
+
+impl Repacker {
+    /// Creates a repacker for a configuration, which is checked for problems first
+    pub fn new(config: Config, seed: u64) -> Result<Self> {
+        config
+            .check()
+            .with_context(|| Failure::new(FailureKind::Config, "Invalid configuration"))?;
+        Ok(Repacker {
+            config,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            repack_dir: PathBuf::from("repack"),
+            editors: EditorRegistry::default(),
+        })
+    }
+
+    /// Adds a custom editor, which runs after the built-in ones unless the `editors` option
+    /// says otherwise
+    pub fn with_editor(mut self, editor: Box<dyn MissionEditor>) -> Self {
+        self.editors.register(editor);
+        self
+    }
+
+    /// Sets the directory of files added to the generated miz, `repack` by default
+    pub fn with_repack_dir(mut self, repack_dir: impl Into<PathBuf>) -> Self {
+        self.repack_dir = repack_dir.into();
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Names of the presets, sorted so that the same seed always generates the same missions
+    pub fn preset_names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.config.preset.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Applies the changes shared by every preset, ie. removing the required modules
+    pub fn prepare_mission(&self, mission: &str, report: &mut MissionReport) -> Result<String> {
+        if self.config.misc.remove_required_modules {
+            remove_required_modules(mission, false, report)
+        } else {
+            Ok(mission.to_owned())
+        }
+    }
+
+    /// Reads the other Lua files the presets can modify (see `LUA_FILES`) from the archive
+    pub fn read_lua_files<R: Read + Seek>(
+        &self,
+        archive: &mut ZipArchive<R>,
+    ) -> Result<BTreeMap<String, String>> {
+        let corrupt = |name: &str| {
+            Failure::new(
+                FailureKind::CorruptArchive,
+                format!("Cannot read {name} file"),
+            )
+        };
+
+        let mut files = BTreeMap::new();
+        for name in LUA_FILES {
+            let mut contents = String::new();
+            match archive.by_name(name) {
+                Ok(mut file) => file
+                    .read_to_string(&mut contents)
+                    .with_context(|| corrupt(name))?,
+                Err(ZipError::FileNotFound) => continue,
+                Err(err) => return Err(err).with_context(|| corrupt(name)),
+            };
+            files.insert(name.to_owned(), contents);
+        }
+        Ok(files)
+    }
+
+    /// Applies a preset to the mission file and the other Lua files read by `read_lua_files`
+    ///
+    /// Copies of the Lua files in the repack directories of the preset replace the ones of
+    /// the archive before the editors run.
+    pub fn generate(
+        &mut self,
+        mission: &str,
+        files: &BTreeMap<String, String>,
+        preset_name: &str,
+    ) -> Result<GeneratedMission> {
+        self.apply_preset(mission, files, preset_name, false)
+    }
+
+    /// Samples the values of a preset without a mission file, to preview the configuration
+    pub fn preview(&mut self, preset_name: &str) -> Result<GeneratedMission> {
+        self.apply_preset("", &BTreeMap::new(), preset_name, true)
+    }
+
+    fn apply_preset(
+        &mut self,
+        mission: &str,
+        files: &BTreeMap<String, String>,
+        preset_name: &str,
+        dry_run: bool,
+    ) -> Result<GeneratedMission> {
+        let Repacker {
+            config,
+            rng,
+            editors,
+            repack_dir,
+            ..
+        } = self;
+        let editors = editors.ordered(config.misc.editors.as_deref())?;
+        let preset = config
+            .preset
+            .get(preset_name)
+            .ok_or_else(|| anyhow!("Preset not found: {preset_name}"))?;
+
+        info!("-> Generating miz preset: {preset_name}");
+
+        // Optionally, choose one of the weather presets
+        let missing_weather = |name: &str| {
+            Failure::new(
+                FailureKind::Config,
+                format!("Preset '{preset_name}' references weather preset '{name}', which does not exist"),
+            )
+        };
+        let mut weather_name = None;
+        if let Some(weather_presets) = &preset.weather {
+            if let Some(name) = weather_presets
+                .iter()
+                .find(|name| !config.weather.contains_key(*name))
+            {
+                return Err(missing_weather(name).into());
+            }
+            let weather_preset = weather_presets
+                .choose_weighted(rng, |weather| config.weather[weather].weight)
+                .with_context(|| {
+                    Failure::new(
+                        FailureKind::Config,
+                        format!("Cannot choose a weather preset for preset '{preset_name}'"),
+                    )
+                })?;
+
+            info!("-> Using weather preset:  {weather_preset}");
+            let weather = config
+                .weather
+                .get_mut(weather_preset)
+                .ok_or_else(|| missing_weather(weather_preset))?;
+
+            weather.randomize_wind_flip(rng);
+            if preset.flip_wind {
+                weather.is_wind_flipped = !weather.is_wind_flipped
+            }
+            weather_name = Some(weather_preset.clone());
+        }
+
+        let mut out_files = files.clone();
+        if !dry_run {
+            let layers = repack_layers(repack_dir, preset_name, weather_name.as_deref())?;
+            for name in LUA_FILES {
+                if let Some(path) = layers
+                    .iter()
+                    .map(|layer| layer.join(name))
+                    .find(|path| path.is_file())
+                {
+                    let contents = fs::read_to_string(&path)
+                        .with_context(|| format!("Cannot read {}", path.display()))?;
+                    out_files.insert(name.to_owned(), contents);
+                }
+            }
+        }
+
+        let mut context = EditContext {
+            preset_name,
+            preset,
+            weather: weather_name
+                .as_ref()
+                .and_then(|name| config.weather.get_key_value(name))
+                .map(|(name, weather)| (name.as_str(), weather)),
+            rng,
+            files: &mut out_files,
+            dry_run,
+        };
+        let mut out_mission = mission.to_owned();
+        for editor in editors {
+            let edit = editor.apply(&out_mission, &mut context)?;
+            for change in &edit.changes {
+                info!("{change}");
+            }
+            out_mission = edit.mission;
+        }
+
+        Ok(GeneratedMission {
+            preset: preset_name.to_owned(),
+            weather: weather_name,
+            mission: out_mission,
+            files: out_files,
+        })
+    }
+
+    /// Names of the archive entries the `remove` patterns of the configuration and the preset
+    /// leave out of the miz of the preset
+    pub fn removed_entries<R: Read + Seek>(
+        &self,
+        archive: &ZipArchive<R>,
+        preset_name: &str,
+    ) -> Result<Vec<String>> {
+        let preset_patterns = self
+            .config
+            .preset
+            .get(preset_name)
+            .map(|preset| preset.remove.as_slice())
+            .unwrap_or_default();
+        let patterns = self
+            .config
+            .misc
+            .remove
+            .iter()
+            .chain(preset_patterns)
+            .map(|pattern| {
+                Pattern::new(pattern).with_context(|| {
+                    Failure::new(
+                        FailureKind::Config,
+                        format!("Invalid remove pattern: {pattern}"),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let match_options = MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+
+        let mut removed: Vec<_> = archive
+            .file_names()
+            .filter(|name| {
+                patterns.iter().any(|pattern| {
+                    // Directory entries also match without their trailing slash
+                    pattern.matches_with(name, match_options)
+                        || pattern.matches_with(name.trim_end_matches('/'), match_options)
+                })
+            })
+            .map(str::to_owned)
+            .collect();
+        removed.sort();
+        Ok(removed)
+    }
+
+    /// Writes a new miz with the generated Lua files, the files of the repack directories of
+    /// the preset and the remaining entries of the original archive
+    pub fn build_miz<R: Read + Seek, W: Write + Seek>(
+        &self,
+        archive: &mut ZipArchive<R>,
+        generated: &GeneratedMission,
+        output: W,
+    ) -> Result<W> {
+        let write_failed = || Failure::new(FailureKind::WriteFailed, "Cannot write the new miz");
+        let mut zip = ZipWriter::new(output);
+        let mut added_files = HashSet::new();
+
+        // Copy the modified mission file
+        add_file(
+            &mut zip,
+            "mission",
+            &mut generated.mission.as_bytes(),
+            &mut added_files,
+        )
+        .with_context(write_failed)?;
+        for (name, contents) in &generated.files {
+            add_file(&mut zip, name, &mut contents.as_bytes(), &mut added_files)
+                .with_context(write_failed)?;
+        }
+
+        // Copy files from the repack dirs, the most specific first. The template values are
+        // only read from the mission if a layer has templates.
+        let vars = OnceCell::new();
+        let template_vars =
+            || vars.get_or_try_init(|| TemplateVars::from_generated(generated, self.seed));
+        let layers = repack_layers(
+            &self.repack_dir,
+            &generated.preset,
+            generated.weather.as_deref(),
+        )?;
+        for layer in &layers {
+            let is_layer_dir = |path: &Path| {
+                // The folders of the other layers are not part of the plain repack dir
+                *layer == self.repack_dir
+                    && path.parent() == Some(&self.repack_dir)
+                    && path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| {
+                            name == COMMON_DIR
+                                || name == WEATHER_DIR
+                                || self.config.preset.contains_key(name)
+                        })
+            };
+            add_repack_files(
+                &mut zip,
+                layer,
+                is_layer_dir,
+                template_vars,
+                &mut added_files,
+            )?;
+        }
+
+        // Copy remaining miz files into the new zip, leaving out the removed ones
+        let removed: HashSet<_> = self
+            .removed_entries(archive, &generated.preset)?
+            .into_iter()
+            .collect();
+        for idx in 0..archive.len() {
+            let mut file = archive.by_index(idx).with_context(|| {
+                Failure::new(
+                    FailureKind::CorruptArchive,
+                    "Cannot read the entries of the miz archive",
+                )
+            })?;
+            let path = file.name().to_owned();
+            if removed.contains(&path) && !added_files.contains(&path) {
+                trace!("   Removed {path}");
+                continue;
+            }
+            add_file(&mut zip, &path, &mut file, &mut added_files).with_context(write_failed)?;
+        }
+
+        zip.finish().with_context(write_failed)
+    }
+
+    /// Generates every preset of a miz, returning the new archives in memory
+    pub fn repack<R: Read + Seek>(
+        &mut self,
+        miz: R,
+        report: &mut MissionReport,
+    ) -> Result<Vec<GeneratedMiz>> {
+        let mut generated_mizs = Vec::new();
+        self.repack_each(miz, report, |generated, _| {
+            generated_mizs.push(generated);
+            Ok(())
+        })?;
+        Ok(generated_mizs)
+    }
+
+    /// Generates every preset of a miz, passing each new archive to `output` as soon as it is
+    /// built, so that only one of them is kept in memory at a time
+    pub fn repack_each<R: Read + Seek>(
+        &mut self,
+        miz: R,
+        report: &mut MissionReport,
+        mut output: impl FnMut(GeneratedMiz, &mut MissionReport) -> Result<()>,
+    ) -> Result<()> {
+        let mut archive = ZipArchive::new(miz).with_context(|| {
+            Failure::new(FailureKind::CorruptArchive, "Cannot read miz archive")
+        })?;
+        let mission = self.prepare_mission(&read_mission_entry(&mut archive)?, report)?;
+        let files = self.read_lua_files(&mut archive)?;
+
+        for preset_name in self.preset_names() {
+            let generated = self.generate(&mission, &files, &preset_name)?;
+            let removed = self.removed_entries(&archive, &preset_name)?;
+            let data = self
+                .build_miz(&mut archive, &generated, Cursor::new(Vec::new()))?
+                .into_inner();
+            output(
+                GeneratedMiz {
+                    mission: generated,
+                    removed,
+                    data,
+                },
+                report,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the `mission` file of an opened miz archive
+pub fn read_mission_entry<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<String> {
+    let mut mission = String::new();
+    archive
+        .by_name("mission")
+        .map_err(anyhow::Error::from)
+        .and_then(|mut file| Ok(file.read_to_string(&mut mission)?))
+        .with_context(|| Failure::new(FailureKind::CorruptArchive, "Cannot read mission file"))?;
+    Ok(mission)
+}
+
+/// Reads the `repack.toml` stored inside the miz archive, if there is one
+pub fn read_embedded_config(path: &Path) -> Result<Option<Vec<u8>>> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let mut data = Vec::new();
+    match archive.by_name("repack.toml") {
+        Ok(mut file) => file.read_to_end(&mut data)?,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(Some(data))
+}
+
+fn add_file<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    path: &str,
+    data: &mut dyn Read,
+    added_files: &mut HashSet<String>,
+) -> Result<bool> {
+    let path = &path.replace('\\', "/");
+    if added_files.contains(path) {
+        return Ok(false);
+    }
+    zip.start_file(path, FileOptions::default().compression_level(Some(9)))?;
+    added_files.insert(path.to_owned());
+    io::copy(data, zip)?;
+    trace!("   Added {path}");
+    Ok(true)
+}
+
+/// Repack directories of a preset, most specific first: `weather/<weather preset>`,
+/// `<preset>`, `_common` and the repack directory itself
+///
+/// The preset names are checked so that the layers stay inside the repack directory.
+fn repack_layers(
+    repack_dir: &Path,
+    preset_name: &str,
+    weather: Option<&str>,
+) -> Result<Vec<PathBuf>> {
+    let invalid_name = || Failure::new(FailureKind::Config, "Cannot find the repack folders");
+    let mut layers = Vec::new();
+    if let Some(weather) = weather {
+        check_preset_name("Weather preset", weather).with_context(invalid_name)?;
+        layers.push(repack_dir.join(WEATHER_DIR).join(weather));
+    }
+    check_preset_name("Preset", preset_name).with_context(invalid_name)?;
+    layers.push(repack_dir.join(preset_name));
+    layers.push(repack_dir.join(COMMON_DIR));
+    layers.push(repack_dir.to_owned());
+    layers.retain(|layer| layer.is_dir());
+    Ok(layers)
+}
+
+fn add_repack_files<'v, W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    repack_dir: &Path,
+    skip_dir: impl Fn(&Path) -> bool,
+    template_vars: impl Fn() -> Result<&'v TemplateVars>,
+    added_files: &mut HashSet<String>,
+) -> Result<()> {
+    if repack_dir.is_dir() {
+        info!("-> Repacking files from {}", repack_dir.display());
+        for entry in WalkDir::new(repack_dir)
+            .into_iter()
+            .filter_entry(|entry| !skip_dir(entry.path()))
+        {
+            let entry = entry?;
+            let fs_path = entry.path();
+            if fs_path.is_file() {
+                let zip_path = entry.path().strip_prefix(repack_dir)?;
+                let zip_path_str = zip_path.to_str().ok_or_else(|| {
+                    anyhow!("Cannot repack non UTF-8 path: {}", fs_path.display())
+                })?;
+                let template_path = zip_path_str.strip_suffix(&format!(".{TEMPLATE_EXTENSION}"));
+                let added = if let Some(zip_path_str) = template_path {
+                    let template = fs::read_to_string(fs_path)
+                        .with_context(|| format!("Cannot read template {}", fs_path.display()))?;
+                    add_file(
+                        zip,
+                        zip_path_str,
+                        &mut render_template(&template, template_vars()?).as_bytes(),
+                        added_files,
+                    )?
+                } else {
+                    add_file(
+                        zip,
+                        zip_path_str,
+                        &mut File::open(fs_path)
+                            .context(format!("Trying to open '{}' as file", fs_path.display()))?,
+                        added_files,
+                    )?
+                };
+                let zip_path_str = template_path.unwrap_or(zip_path_str);
+                if added {
+                    info!("   Repacked {}", zip_path_str);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_existing_layers_most_specific_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let repack_dir = dir.path().join("repack");
+        for layer in ["weather/rain", "morning", COMMON_DIR] {
+            fs::create_dir_all(repack_dir.join(layer)).unwrap();
+        }
+
+        let layers = repack_layers(&repack_dir, "morning", Some("rain")).unwrap();
+        assert_eq!(
+            layers,
+            [
+                repack_dir.join("weather/rain"),
+                repack_dir.join("morning"),
+                repack_dir.join(COMMON_DIR),
+                repack_dir.clone(),
+            ]
+        );
+
+        let layers = repack_layers(&repack_dir, "evening", Some("clear")).unwrap();
+        assert_eq!(layers, [repack_dir.join(COMMON_DIR), repack_dir.clone()]);
+    }
+
+    #[test]
+    fn layers_stay_inside_the_repack_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let repack_dir = dir.path().join("repack");
+        fs::create_dir_all(&repack_dir).unwrap();
+        fs::create_dir_all(dir.path().join("secrets")).unwrap();
+
+        for (preset, weather) in [
+            ("../secrets", None),
+            ("..", None),
+            ("morning", Some("../../secrets")),
+        ] {
+            let err = repack_layers(&repack_dir, preset, weather).unwrap_err();
+            assert_eq!(FailureKind::of(&err), Some(FailureKind::Config));
+        }
+    }
+
+    #[test]
+    fn rejects_configs_with_problems() {
+        for toml in [
+            "[misc]\n[weather]\n[preset.morning]\ntime = \"06:00\"\nweather = [\"missing\"]\n",
+            "[misc]\n[weather.calm]\nweight = 0.0\n[preset.morning]\ntime = \"06:00\"\nweather = [\"calm\"]\n",
+        ] {
+            let config: Config = toml::from_str(toml).unwrap();
+            let err = Repacker::new(config, 1).err().unwrap();
+            assert_eq!(FailureKind::of(&err), Some(FailureKind::Config));
+        }
+
+        let config: Config = toml::from_str(
+            "[misc]\n[weather.clear]\ncloud_preset = \"Preset1\"\n[preset.morning]\ntime = \"06:00\"\nweather = [\"clear\"]\n",
+        )
+        .unwrap();
+        let mut repacker = Repacker::new(config, 1).unwrap();
+        let generated = repacker.preview("morning").unwrap();
+        assert_eq!(generated.weather.as_deref(), Some("clear"));
+    }
+}
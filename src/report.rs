@@ -0,0 +1,67 @@
+use crate::inspect::MissionEnvironment;
+use anyhow::{Context, Result};
+use log::warn;
+use serde_derive::Serialize;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Machine-readable summary of a run, written with `--report`
+#[derive(Debug, Default, Serialize)]
+pub struct RunReport {
+    pub dry_run: bool,
+    pub missions: Vec<MissionReport>,
+    pub error: Option<String>,
+}
+
+/// What happened to a single input miz
+#[derive(Debug, Serialize)]
+pub struct MissionReport {
+    pub input: PathBuf,
+    pub seed: Option<u64>,
+    pub presets: Vec<PresetReport>,
+    pub warnings: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// A generated mission, with the values that were sampled for it
+#[derive(Debug, Serialize)]
+pub struct PresetReport {
+    pub preset: String,
+    pub weather: Option<String>,
+    pub output_path: PathBuf,
+    pub written: bool,
+    pub values: Option<MissionEnvironment>,
+}
+
+impl RunReport {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file =
+            File::create(path).with_context(|| format!("Cannot create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl MissionReport {
+    pub fn new(input: &Path) -> Self {
+        MissionReport {
+            input: input.to_owned(),
+            seed: None,
+            presets: Vec::new(),
+            warnings: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Prints a warning and records it in the report
+    pub fn warn(&mut self, message: String) {
+        warn!("{message}");
+        self.warnings.push(message);
+    }
+}
@@ -0,0 +1,354 @@
+use crate::{
+    editor::{Change, Edit, EditContext, MissionEditor},
+    failure::{Failure, FailureKind},
+    inspect::MissionEnvironment,
+    lua::{parse_lua_assignment, to_lua_assignment, LuaKey, LuaTable, LuaValue},
+};
+use anyhow::{anyhow, Context, Result};
+use mlua::{HookTriggers, Lua, LuaOptions, StdLib};
+use std::{cell::Cell, collections::HashMap, fs, path::Path};
+
+/// Number of Lua VM instructions a script can run before it fails
+const SCRIPT_INSTRUCTION_LIMIT: u64 = 1_000_000_000;
+
+/// How many instructions run between checks of the limit
+const INSTRUCTION_HOOK_INTERVAL: u32 = 10_000;
+
+/// Runs the Lua script of the preset, which can modify the mission table in any way
+///
+/// The script sees the mission as the `mission` global, the `options` and `warehouses` files
+/// as globals of the same name, the values set by the other editors as `weather`, and the
+/// names of the presets as `preset` and `weather_preset`. It either returns the modified
+/// mission or changes the tables in place.
+pub struct ScriptEditor;
+
+impl MissionEditor for ScriptEditor {
+    fn name(&self) -> &'static str {
+        "script"
+    }
+
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["script"]
+    }
+
+    fn apply(&self, mission: &str, context: &mut EditContext) -> Result<Edit> {
+        let Some(script_path) = context.preset.script.clone() else {
+            return Ok(Edit::unchanged(mission));
+        };
+        let mut edit = if context.dry_run {
+            // Without a mission, there is nothing to run the script on
+            Edit::unchanged(mission)
+        } else {
+            let mission = run_script(Path::new(&script_path), mission, context)
+                .with_context(|| format!("Failed to run script {script_path}"))?;
+            Edit {
+                mission,
+                changes: Vec::new(),
+            }
+        };
+        edit.changes.push(Change::new("Script", script_path));
+        Ok(edit)
+    }
+}
+
+/// Runs a Lua script on the mission file, returning the new mission
+///
+/// The other Lua files of the context are updated in place. Files the script does not change
+/// are kept as they were, byte for byte.
+pub fn run_script(script_path: &Path, mission: &str, context: &mut EditContext) -> Result<String> {
+    let script = fs::read_to_string(script_path).with_context(|| {
+        Failure::new(
+            FailureKind::Config,
+            format!(
+                "Cannot read script {}, scripts are relative to the folder of the miz",
+                script_path.display()
+            ),
+        )
+    })?;
+    let (mission_name, mission_value) = parse_lua_assignment(mission)?;
+    let files = context
+        .files
+        .iter()
+        .map(|(file, source)| {
+            let assignment =
+                parse_lua_assignment(source).with_context(|| format!("Cannot read {file} file"))?;
+            Ok((file.clone(), assignment))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let lua = new_sandbox(SCRIPT_INSTRUCTION_LIMIT)?;
+    let globals = lua.globals();
+    globals.set(mission_name.as_str(), to_lua(&lua, &mission_value)?)?;
+    for (_, (name, value)) in &files {
+        globals.set(name.as_str(), to_lua(&lua, value)?)?;
+    }
+    globals.set("preset", context.preset_name)?;
+    globals.set("weather_preset", context.weather.map(|(name, _)| name))?;
+    let environment = serde_json::to_value(MissionEnvironment::from_mission(&mission_value))?;
+    globals.set("weather", json_to_lua(&lua, &environment)?)?;
+
+    let result: mlua::Value = lua
+        .load(&script)
+        .set_name(script_path.display().to_string())
+        .eval()
+        .map_err(|err| anyhow!("{err}"))?;
+    let result = match result {
+        mlua::Value::Nil => globals.get(mission_name.as_str())?,
+        result => result,
+    };
+
+    for (file, (name, value)) in files {
+        let new_value = from_lua(globals.get(name.as_str())?, Some(&value))
+            .with_context(|| format!("Cannot write {file} file"))?;
+        if new_value != value {
+            context
+                .files
+                .insert(file, to_lua_assignment(&name, &new_value));
+        }
+    }
+
+    let new_mission = from_lua(result, Some(&mission_value))?;
+    Ok(if new_mission == mission_value {
+        mission.to_owned()
+    } else {
+        to_lua_assignment(&mission_name, &new_mission)
+    })
+}
+
+/// Creates the Lua state scripts run in
+///
+/// Scripts only edit tables, so they get no access to files, processes or other modules. They
+/// also fail after `instruction_limit` VM instructions, so that an endless loop does not hang
+/// unattended runs.
+fn new_sandbox(instruction_limit: u64) -> Result<Lua> {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    )?;
+    {
+        let globals = lua.globals();
+        globals.set("dofile", mlua::Value::Nil)?;
+        globals.set("loadfile", mlua::Value::Nil)?;
+    }
+
+    let instructions = Cell::new(0);
+    let triggers = HookTriggers::new().every_nth_instruction(INSTRUCTION_HOOK_INTERVAL);
+    lua.set_hook(triggers, move |_, _| {
+        instructions.set(instructions.get() + u64::from(INSTRUCTION_HOOK_INTERVAL));
+        if instructions.get() > instruction_limit {
+            return Err(mlua::Error::RuntimeError(format!(
+                "the script ran more than {instruction_limit} instructions, it may be stuck in an endless loop"
+            )));
+        }
+        Ok(())
+    });
+    Ok(lua)
+}
+
+fn to_lua<'lua>(lua: &'lua Lua, value: &LuaValue) -> Result<mlua::Value<'lua>> {
+    Ok(match value {
+        LuaValue::Nil => mlua::Value::Nil,
+        LuaValue::Bool(value) => mlua::Value::Boolean(*value),
+        LuaValue::Integer(value) => mlua::Value::Integer(*value),
+        LuaValue::Number(value) => mlua::Value::Number(*value),
+        LuaValue::String(value) => mlua::Value::String(lua.create_string(value)?),
+        LuaValue::Table(entries) => {
+            let table = lua.create_table()?;
+            for (key, value) in entries {
+                match key {
+                    LuaKey::Index(index) => table.raw_set(*index, to_lua(lua, value)?)?,
+                    LuaKey::Name(name) => table.raw_set(name.as_str(), to_lua(lua, value)?)?,
+                }
+            }
+            mlua::Value::Table(table)
+        }
+    })
+}
+
+fn json_to_lua<'lua>(lua: &'lua Lua, value: &serde_json::Value) -> Result<mlua::Value<'lua>> {
+    Ok(match value {
+        serde_json::Value::Null => mlua::Value::Nil,
+        serde_json::Value::Bool(value) => mlua::Value::Boolean(*value),
+        serde_json::Value::Number(value) => match value.as_i64() {
+            Some(value) => mlua::Value::Integer(value),
+            None => mlua::Value::Number(value.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(value) => mlua::Value::String(lua.create_string(value)?),
+        serde_json::Value::Array(values) => {
+            let table = lua.create_table()?;
+            for (index, value) in values.iter().enumerate() {
+                table.raw_set(index + 1, json_to_lua(lua, value)?)?;
+            }
+            mlua::Value::Table(table)
+        }
+        serde_json::Value::Object(entries) => {
+            let table = lua.create_table()?;
+            for (key, value) in entries {
+                table.raw_set(key.as_str(), json_to_lua(lua, value)?)?;
+            }
+            mlua::Value::Table(table)
+        }
+    })
+}
+
+/// Converts a value back from Lua
+///
+/// Lua does not keep the order of table entries, so entries that were already in the original
+/// table keep their position, and new entries are added after them in sorted order.
+///
+/// Lua 5.1 stores every number as a double, so numbers the script did not change keep their
+/// original value, which may be an integer a double cannot hold exactly.
+fn from_lua(value: mlua::Value, original: Option<&LuaValue>) -> Result<LuaValue> {
+    let unchanged_number = |value: f64| {
+        original
+            .filter(|original| original.as_f64() == Some(value))
+            .cloned()
+    };
+    Ok(match value {
+        mlua::Value::Nil => LuaValue::Nil,
+        mlua::Value::Boolean(value) => LuaValue::Bool(value),
+        mlua::Value::Integer(value) => {
+            unchanged_number(value as f64).unwrap_or(LuaValue::Integer(value))
+        }
+        mlua::Value::Number(value) => unchanged_number(value).unwrap_or(LuaValue::Number(value)),
+        mlua::Value::String(value) => LuaValue::String(value.to_str()?.to_owned()),
+        mlua::Value::Table(table) => {
+            let original = original.and_then(LuaValue::as_table);
+            let positions: HashMap<&LuaKey, usize> = original
+                .into_iter()
+                .flatten()
+                .enumerate()
+                .map(|(position, (key, _))| (key, position))
+                .collect();
+
+            let mut entries = LuaTable::new();
+            for pair in table.pairs::<mlua::Value, mlua::Value>() {
+                let (key, value) = pair?;
+                let key = match key {
+                    mlua::Value::Integer(index) => LuaKey::Index(index),
+                    mlua::Value::Number(index) if index.fract() == 0.0 => {
+                        LuaKey::Index(index as i64)
+                    }
+                    mlua::Value::String(name) => LuaKey::Name(name.to_str()?.to_owned()),
+                    key => return Err(anyhow!("Unsupported table key: {}", key.type_name())),
+                };
+                let original_value = positions
+                    .get(&key)
+                    .map(|&position| &original.unwrap()[position].1);
+                let value = from_lua(value, original_value)?;
+                entries.push((key, value));
+            }
+
+            let position = |key: &LuaKey| positions.get(key).copied().unwrap_or(usize::MAX);
+            entries.sort_by(|(a, _), (b, _)| position(a).cmp(&position(b)).then(a.cmp(b)));
+            LuaValue::Table(entries)
+        }
+        value => {
+            return Err(anyhow!(
+                "Cannot store a {} in a miz file",
+                value.type_name()
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Preset;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::collections::BTreeMap;
+
+    const MISSION: &str = "mission = \n{\n    [\"groupId\"] = 9007199254740993,\n    [\"start_time\"] = 28800,\n} -- end of mission\n";
+
+    fn run(script: &str, files: &mut BTreeMap<String, String>) -> Result<String> {
+        let dir = tempfile::tempdir()?;
+        let script_path = dir.path().join("script.lua");
+        fs::write(&script_path, script)?;
+        let preset: Preset = toml::from_str("time = \"08:00\"")?;
+        let mut context = EditContext {
+            preset_name: "morning",
+            preset: &preset,
+            weather: None,
+            rng: &mut StdRng::seed_from_u64(0),
+            files,
+            dry_run: false,
+        };
+        run_script(&script_path, MISSION, &mut context)
+    }
+
+    #[test]
+    fn keeps_unchanged_files_as_they_are() {
+        let options = "options = { difficulty = { labels = 1 } }";
+        let mut files = BTreeMap::from([("options".to_owned(), options.to_owned())]);
+        assert_eq!(
+            run("local x = mission.groupId", &mut files).unwrap(),
+            MISSION
+        );
+        assert_eq!(files["options"], options);
+    }
+
+    #[test]
+    fn keeps_large_integers_exact() {
+        let mut files = BTreeMap::new();
+        let mission = run("mission.start_time = mission.start_time + 3600", &mut files).unwrap();
+        assert!(
+            mission.contains("[\"groupId\"] = 9007199254740993,"),
+            "{mission}"
+        );
+        assert!(mission.contains("[\"start_time\"] = 32400,"), "{mission}");
+    }
+
+    #[test]
+    fn updates_the_other_files() {
+        let mut files = BTreeMap::from([("options".to_owned(), "options = {}".to_owned())]);
+        run("options.labels = 0", &mut files).unwrap();
+        assert_eq!(
+            files["options"],
+            "options = \n{\n    [\"labels\"] = 0,\n} -- end of options\n"
+        );
+    }
+
+    #[test]
+    fn fails_with_a_missing_script() {
+        let dir = tempfile::tempdir().unwrap();
+        let preset: Preset = toml::from_str("time = \"08:00\"").unwrap();
+        let mut context = EditContext {
+            preset_name: "morning",
+            preset: &preset,
+            weather: None,
+            rng: &mut StdRng::seed_from_u64(0),
+            files: &mut BTreeMap::new(),
+            dry_run: false,
+        };
+        let err = run_script(&dir.path().join("missing.lua"), MISSION, &mut context).unwrap_err();
+        assert_eq!(FailureKind::of(&err), Some(FailureKind::Config));
+    }
+
+    #[test]
+    fn stops_endless_loops() {
+        let lua = new_sandbox(1_000_000).unwrap();
+        let err = lua.load("while true do end").exec().unwrap_err();
+        assert!(err.to_string().contains("endless loop"), "{err}");
+
+        let lua = new_sandbox(1_000_000).unwrap();
+        let sum: i64 = lua
+            .load("local sum = 0 for i = 1, 1000 do sum = sum + i end return sum")
+            .eval()
+            .unwrap();
+        assert_eq!(sum, 500_500);
+    }
+
+    #[test]
+    fn cannot_access_files_or_processes() {
+        for script in [
+            "io.open('mission')",
+            "os.execute('echo')",
+            "dofile('script.lua')",
+            "loadfile('script.lua')",
+            "require('io')",
+        ] {
+            assert!(run(script, &mut BTreeMap::new()).is_err(), "{script}");
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{
+    failure::{Failure, FailureKind},
+    inspect::MissionEnvironment,
+    lua::parse_lua_assignment,
+    repacker::GeneratedMission,
+};
+use anyhow::{Context, Result};
+use log::warn;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+/// Extension of repack files whose placeholders are replaced before adding them to the miz,
+/// ie. `variant.lua.tmpl` is added as `variant.lua`
+pub const TEMPLATE_EXTENSION: &str = "tmpl";
+
+static PLACEHOLDER_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{\{\s*(\w+)\s*\}\}").unwrap());
+
+/// Values available to the placeholders of a repack template, ie. `{{preset}}`
+pub struct TemplateVars {
+    pub preset: String,
+    pub weather: Option<String>,
+    /// Start time of the generated mission, as `HH:MM:SS`
+    pub start_time: Option<String>,
+    /// QNH of the generated mission, in mmHg
+    pub qnh: Option<f64>,
+    pub seed: u64,
+}
+
+impl TemplateVars {
+    /// Reads the values of a generated mission
+    pub fn from_generated(generated: &GeneratedMission, seed: u64) -> Result<Self> {
+        let (_, mission) = parse_lua_assignment(&generated.mission).with_context(|| {
+            Failure::new(
+                FailureKind::CorruptArchive,
+                "Cannot read the mission values for the repack templates",
+            )
+        })?;
+        let environment = MissionEnvironment::from_mission(&mission);
+        Ok(TemplateVars {
+            preset: generated.preset.clone(),
+            weather: generated.weather.clone(),
+            start_time: environment.start_time,
+            qnh: environment.qnh,
+            seed,
+        })
+    }
+}
+
+/// Replaces the placeholders of a template with their values
+///
+/// Unknown placeholders are left as they are with a warning, since `{{` is also valid Lua,
+/// ie. `{{1, 2}}`.
+pub fn render_template(template: &str, vars: &TemplateVars) -> String {
+    PLACEHOLDER_REGEX
+        .replace_all(template, |cap: &Captures| match &cap[1] {
+            "preset" => vars.preset.clone(),
+            "weather" => vars.weather.as_deref().unwrap_or("none").to_owned(),
+            "start_time" => vars.start_time.clone().unwrap_or_default(),
+            "qnh" => vars.qnh.map(|qnh| qnh.to_string()).unwrap_or_default(),
+            "seed" => vars.seed.to_string(),
+            _ => {
+                warn!(
+                    "Unknown template placeholder {}, leaving it as it is",
+                    &cap[0]
+                );
+                cap[0].to_owned()
+            }
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn vars() -> TemplateVars {
+        TemplateVars {
+            preset: "morning".to_owned(),
+            weather: Some("rain".to_owned()),
+            start_time: Some("08:00:00".to_owned()),
+            qnh: Some(755.5),
+            seed: 42,
+        }
+    }
+
+    #[test]
+    fn replaces_every_placeholder() {
+        let template = "{{preset}} {{ weather }} {{start_time}} {{qnh}} {{seed}}";
+        assert_eq!(
+            render_template(template, &vars()),
+            "morning rain 08:00:00 755.5 42"
+        );
+    }
+
+    #[test]
+    fn replaces_missing_values() {
+        let vars = TemplateVars {
+            weather: None,
+            start_time: None,
+            qnh: None,
+            ..vars()
+        };
+        assert_eq!(
+            render_template("{{weather}}/{{start_time}}/{{qnh}}", &vars),
+            "none//"
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_placeholders_and_lua_tables() {
+        let template = "VARIANT = {{unknown}}, POINTS = {{1, 2}, {3, 4}}";
+        assert_eq!(render_template(template, &vars()), template);
+    }
+
+    #[test]
+    fn reads_the_values_of_the_generated_mission() {
+        let generated = GeneratedMission {
+            preset: "morning".to_owned(),
+            weather: None,
+            mission: "mission = { start_time = 28800, weather = { qnh = 760 } }".to_owned(),
+            files: BTreeMap::new(),
+        };
+        let vars = TemplateVars::from_generated(&generated, 7).unwrap();
+        assert_eq!(vars.start_time.as_deref(), Some("08:00:00"));
+        assert_eq!(vars.qnh, Some(760.0));
+
+        let generated = GeneratedMission {
+            mission: "mission = { text = [[long string]] }".to_owned(),
+            ..generated
+        };
+        let err = TemplateVars::from_generated(&generated, 7).err().unwrap();
+        assert_eq!(FailureKind::of(&err), Some(FailureKind::CorruptArchive));
+    }
+}
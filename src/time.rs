@@ -1,9 +1,19 @@
-use crate::{config::Preset, misc::INDENT};
+use crate::{
+    config::Preset,
+    editor::{Change, Edit, EditContext, MissionEditor},
+    failure::missing_key_error,
+    misc::INDENT,
+};
 use anyhow::{anyhow, Context, Result};
 use once_cell::sync::Lazy;
 use regex::{Captures, Regex, RegexBuilder};
 
-pub fn modify_time(mission: &str, preset: &Preset, dry_run: bool) -> Result<String> {
+pub fn modify_time(
+    mission: &str,
+    preset: &Preset,
+    changes: &mut Vec<Change>,
+    dry_run: bool,
+) -> Result<String> {
     // Note: we HAVE to replace the entry that is indented by exactly 4 spaces, because
     // there are other keys named "start_time" which we DON'T want to replace.
     static REGEX: Lazy<Regex> = Lazy::new(|| {
@@ -13,47 +23,88 @@ pub fn modify_time(mission: &str, preset: &Preset, dry_run: bool) -> Result<Stri
             .unwrap()
     });
 
-    let time: Vec<&str> = preset.time.split(':').collect();
+    let (hours, minutes, seconds) = parse_time(&preset.time)?;
+
+    if !dry_run && !REGEX.is_match(mission) {
+        return Err(missing_key_error("start_time key"));
+    }
+
+    changes.push(Change::new(
+        "Start time",
+        format!("{:02}:{:02}:{:02}", hours, minutes, seconds),
+    ));
+    Ok(REGEX
+        .replace(mission, |cap: &Captures| {
+            // And de-normalize it back into seconds
+            format!("{} = {},", &cap[1], hours * 3600 + minutes * 60 + seconds)
+        })
+        .into_owned())
+}
+
+/// Sets the start time of the mission
+pub struct TimeEditor;
+
+impl MissionEditor for TimeEditor {
+    fn name(&self) -> &'static str {
+        "time"
+    }
+
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["time"]
+    }
+
+    fn apply(&self, mission: &str, context: &mut EditContext) -> Result<Edit> {
+        let mut changes = Vec::new();
+        let mission = modify_time(mission, context.preset, &mut changes, context.dry_run)?;
+        Ok(Edit { mission, changes })
+    }
+}
+
+/// Parses a `hh[:mm[:ss]]` time string into normalized hours, minutes and seconds
+pub fn parse_time(time_str: &str) -> Result<(i32, i32, i32)> {
+    let (mut hours, mut minutes, mut seconds) = parse_time_parts(time_str)?;
+
+    // Normalize the time value by allowing overflow
+    minutes += seconds / 60;
+    seconds %= 60;
+    hours += minutes / 60;
+    minutes %= 60;
+    hours %= 24;
+
+    Ok((hours, minutes, seconds))
+}
+
+/// Checks that a `hh[:mm[:ss]]` time string is a valid time of day, without overflowing
+/// minutes or seconds
+pub fn check_time(time_str: &str) -> Result<()> {
+    let (hours, minutes, seconds) = parse_time_parts(time_str)?;
+    if !(0..24).contains(&hours) || !(0..60).contains(&minutes) || !(0..60).contains(&seconds) {
+        return Err(anyhow!("'{time_str}' is not between 00:00:00 and 23:59:59"));
+    }
+    Ok(())
+}
+
+fn parse_time_parts(time_str: &str) -> Result<(i32, i32, i32)> {
+    let time: Vec<&str> = time_str.split(':').collect();
 
     if time.is_empty() || time.len() > 3 {
-        Err(anyhow!("Invalid time format: {}", preset.time))
-    } else {
-        let mut hours: i32 = time
-            .first()
-            .map(|hours| hours.parse())
-            .unwrap()
-            .context(format!("cannot read hours from time: '{}'", preset.time))?;
-        let mut minutes: i32 = time
-            .get(1)
-            .map(|minutes| minutes.parse())
-            .unwrap_or(Ok(0))
-            .context(format!("cannot read minutes from time: '{}'", preset.time))?;
-        let mut seconds: i32 = time
-            .get(2)
-            .map(|seconds| seconds.parse())
-            .unwrap_or(Ok(0))
-            .context(format!("cannot read seconds from time: '{}'", preset.time))?;
-
-        // Normalize the time value by allowing overflow
-        minutes += seconds / 60;
-        seconds %= 60;
-        hours += minutes / 60;
-        minutes %= 60;
-        hours %= 24;
-
-        if !dry_run && !REGEX.is_match(mission) {
-            return Err(anyhow!("Could not find start_time key in mission file"));
-        }
-
-        println!(
-            "   Start time:            {:02}:{:02}:{:02}",
-            hours, minutes, seconds
-        );
-        Ok(REGEX
-            .replace(mission, |cap: &Captures| {
-                // And de-normalize it back into seconds
-                format!("{} = {},", &cap[1], hours * 3600 + minutes * 60 + seconds)
-            })
-            .into_owned())
+        return Err(anyhow!("Invalid time format: {}", time_str));
     }
+    let hours: i32 = time
+        .first()
+        .map(|hours| hours.parse())
+        .unwrap()
+        .context(format!("cannot read hours from time: '{}'", time_str))?;
+    let minutes: i32 = time
+        .get(1)
+        .map(|minutes| minutes.parse())
+        .unwrap_or(Ok(0))
+        .context(format!("cannot read minutes from time: '{}'", time_str))?;
+    let seconds: i32 = time
+        .get(2)
+        .map(|seconds| seconds.parse())
+        .unwrap_or(Ok(0))
+        .context(format!("cannot read seconds from time: '{}'", time_str))?;
+
+    Ok((hours, minutes, seconds))
 }
@@ -1,30 +1,164 @@
-use crate::config::Weather;
+use crate::editor::{Edit, EditContext, MissionEditor};
 use anyhow::Result;
 
 mod clouds;
 mod misc;
 mod wind;
 
-pub fn modify_weather(
-    mission: String,
-    preset_name: &str,
-    weather: &Weather,
-    dry_run: bool,
-) -> Result<String> {
-    let mut wind_ground_speed = 0.0;
-    let mut wind_2000m_speed = 0.0;
-    let mission = clouds::modify_cloud_preset(&mission, preset_name, weather, dry_run)?;
-    let mission = clouds::modify_cloud_base(&mission, weather, dry_run)?;
-    let mission = wind::modify_ground_wind(&mission, weather, &mut wind_ground_speed, dry_run)?;
-    let mission = wind::modify_2000m_wind(
-        &mission,
-        weather,
-        wind_ground_speed,
-        &mut wind_2000m_speed,
-        dry_run,
-    )?;
-    let mission = wind::modify_8000m_wind(&mission, weather, wind_2000m_speed, dry_run)?;
-    let mission = misc::modify_temp(&mission, weather, dry_run)?;
-    let mission = misc::modify_qnh(&mission, weather, dry_run)?;
-    Ok(mission.into_owned())
+pub use clouds::cloud_base_range;
+
+/// Sets the cloud preset and cloud base of the weather preset
+pub struct CloudsEditor;
+
+impl MissionEditor for CloudsEditor {
+    fn name(&self) -> &'static str {
+        "clouds"
+    }
+
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["cloud_preset", "cloud_base_min", "cloud_base_max"]
+    }
+
+    fn apply(&self, mission: &str, context: &mut EditContext) -> Result<Edit> {
+        let Some((preset_name, weather)) = context.weather else {
+            return Ok(Edit::unchanged(mission));
+        };
+        let mut changes = Vec::new();
+        let mission = clouds::modify_cloud_preset(
+            mission,
+            preset_name,
+            weather,
+            &mut changes,
+            context.dry_run,
+        )?;
+        let mission = clouds::modify_cloud_base(
+            &mission,
+            weather,
+            context.rng,
+            &mut changes,
+            context.dry_run,
+        )?;
+        Ok(Edit {
+            mission: mission.into_owned(),
+            changes,
+        })
+    }
+}
+
+/// Sets the wind speed and heading on the ground, at 2000m and at 8000m
+pub struct WindEditor;
+
+impl MissionEditor for WindEditor {
+    fn name(&self) -> &'static str {
+        "wind"
+    }
+
+    fn config_keys(&self) -> &'static [&'static str] {
+        &[
+            "wind_ground_speed_min",
+            "wind_ground_speed_max",
+            "wind_ground_heading_min",
+            "wind_ground_heading_max",
+            "wind_2000m_increase_speed_min",
+            "wind_2000m_increase_speed_max",
+            "wind_2000m_heading_min",
+            "wind_2000m_heading_max",
+            "wind_8000m_increase_speed_min",
+            "wind_8000m_increase_speed_max",
+            "wind_8000m_heading_min",
+            "wind_8000m_heading_max",
+            "wind_flip_chance",
+            "flip_wind",
+        ]
+    }
+
+    fn apply(&self, mission: &str, context: &mut EditContext) -> Result<Edit> {
+        let Some((_, weather)) = context.weather else {
+            return Ok(Edit::unchanged(mission));
+        };
+        let mut changes = Vec::new();
+        let mut wind_ground_speed = 0.0;
+        let mut wind_2000m_speed = 0.0;
+        let mission = wind::modify_ground_wind(
+            mission,
+            weather,
+            context.rng,
+            &mut wind_ground_speed,
+            &mut changes,
+            context.dry_run,
+        )?;
+        let mission = wind::modify_2000m_wind(
+            &mission,
+            weather,
+            context.rng,
+            wind_ground_speed,
+            &mut wind_2000m_speed,
+            &mut changes,
+            context.dry_run,
+        )?;
+        let mission = wind::modify_8000m_wind(
+            &mission,
+            weather,
+            context.rng,
+            wind_2000m_speed,
+            &mut changes,
+            context.dry_run,
+        )?;
+        Ok(Edit {
+            mission: mission.into_owned(),
+            changes,
+        })
+    }
+}
+
+/// Sets the temperature at sea level
+pub struct TemperatureEditor;
+
+impl MissionEditor for TemperatureEditor {
+    fn name(&self) -> &'static str {
+        "temperature"
+    }
+
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["temp_min", "temp_max"]
+    }
+
+    fn apply(&self, mission: &str, context: &mut EditContext) -> Result<Edit> {
+        let Some((_, weather)) = context.weather else {
+            return Ok(Edit::unchanged(mission));
+        };
+        let mut changes = Vec::new();
+        let mission =
+            misc::modify_temp(mission, weather, context.rng, &mut changes, context.dry_run)?;
+        Ok(Edit {
+            mission: mission.into_owned(),
+            changes,
+        })
+    }
+}
+
+/// Sets the atmospheric pressure at sea level
+pub struct QnhEditor;
+
+impl MissionEditor for QnhEditor {
+    fn name(&self) -> &'static str {
+        "qnh"
+    }
+
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["qnh_min", "qnh_max"]
+    }
+
+    fn apply(&self, mission: &str, context: &mut EditContext) -> Result<Edit> {
+        let Some((_, weather)) = context.weather else {
+            return Ok(Edit::unchanged(mission));
+        };
+        let mut changes = Vec::new();
+        let mission =
+            misc::modify_qnh(mission, weather, context.rng, &mut changes, context.dry_run)?;
+        Ok(Edit {
+            mission: mission.into_owned(),
+            changes,
+        })
+    }
 }
@@ -1,27 +1,78 @@
-use crate::config::Weather;
-use anyhow::{anyhow, Context, Result};
+use crate::{
+    config::Weather,
+    editor::Change,
+    failure::{missing_key_error, Failure, FailureKind},
+};
+use anyhow::{Context, Result};
 use once_cell::sync::Lazy;
+use rand::Rng;
 use regex::{Captures, Regex};
 use std::borrow::Cow;
 
+/// Cloud presets known to DCS, along with the minimum and maximum cloud base (in meters)
+/// that DCS allows for each of them
+const CLOUD_PRESETS: &[(&str, i32, i32)] = &[
+    ("Preset1", 840, 4200),
+    ("Preset2", 1260, 2520),
+    ("Preset3", 840, 2520),
+    ("Preset4", 1260, 2520),
+    ("Preset5", 1260, 4620),
+    ("Preset6", 1260, 4200),
+    ("Preset7", 1680, 5040),
+    ("Preset8", 3780, 5460),
+    ("Preset9", 1680, 3780),
+    ("Preset10", 1260, 4200),
+    ("Preset11", 2520, 5460),
+    ("Preset12", 1680, 3360),
+    ("Preset13", 1680, 3360),
+    ("Preset14", 1680, 3360),
+    ("Preset15", 840, 5040),
+    ("Preset16", 1260, 4200),
+    ("Preset17", 0, 2520),
+    ("Preset18", 0, 3780),
+    ("Preset19", 0, 2940),
+    ("Preset20", 0, 3780),
+    ("Preset21", 1260, 4200),
+    ("Preset22", 420, 4200),
+    ("Preset23", 840, 3360),
+    ("Preset24", 420, 2520),
+    ("Preset25", 420, 3360),
+    ("Preset26", 420, 2940),
+    ("Preset27", 420, 2520),
+    ("RainyPreset1", 420, 2940),
+    ("RainyPreset2", 840, 2520),
+    ("RainyPreset3", 840, 2520),
+];
+
+/// Returns the allowed cloud base range of a DCS cloud preset, or `None` if the preset is unknown
+pub fn cloud_base_range(cloud_preset: &str) -> Option<(i32, i32)> {
+    CLOUD_PRESETS
+        .iter()
+        .find(|(name, _, _)| *name == cloud_preset)
+        .map(|&(_, min, max)| (min, max))
+}
+
 pub fn modify_cloud_preset<'a>(
     mission: &'a str,
     preset_name: &'a str,
     weather: &Weather,
+    changes: &mut Vec<Change>,
     dry_run: bool,
 ) -> Result<Cow<'a, str>> {
     static REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(\["preset"\]) = ".+","#).unwrap());
 
     if !dry_run && !REGEX.is_match(mission) {
-        return Err(anyhow!("Could not find cloud preset in mission file"));
+        return Err(missing_key_error("cloud preset"));
     }
 
-    let cloud_preset = weather
-        .cloud_preset
-        .as_ref()
-        .with_context(|| format!("Cloud preset not defined in weather key: {preset_name}"))?;
+    let cloud_preset = weather.cloud_preset.as_ref().with_context(|| {
+        Failure::new(
+            FailureKind::Config,
+            format!("Cloud preset not defined in weather key: {preset_name}"),
+        )
+    })?;
 
-    println!("   Cloud preset:          {cloud_preset}",);
+    changes.push(Change::new("Cloud preset", cloud_preset.as_str()));
     Ok(REGEX.replace(mission, |cap: &Captures| {
         format!("{} = \"{}\",", &cap[1], cloud_preset)
     }))
@@ -30,15 +81,17 @@ pub fn modify_cloud_preset<'a>(
 pub fn modify_cloud_base<'a>(
     mission: &'a str,
     weather: &Weather,
+    rng: &mut impl Rng,
+    changes: &mut Vec<Change>,
     dry_run: bool,
 ) -> Result<Cow<'a, str>> {
     static REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(\["base"\]) = [\d\.]+,"#).unwrap());
 
-    if let Some(cloud_base) = weather.random_cloud_base() {
+    if let Some(cloud_base) = weather.random_cloud_base(rng) {
         if !dry_run && !REGEX.is_match(mission) {
-            return Err(anyhow!("Could not find cloud base key in mission file"));
+            return Err(missing_key_error("cloud base key"));
         }
-        println!("   Cloud base:            {} meters", cloud_base);
+        changes.push(Change::new("Cloud base", format!("{} meters", cloud_base)));
         Ok(REGEX.replace(mission, |cap: &Captures| {
             format!("{} = {},", &cap[1], cloud_base)
         }))
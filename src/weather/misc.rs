@@ -1,18 +1,25 @@
-use crate::config::Weather;
-use anyhow::{anyhow, Result};
+use crate::{config::Weather, editor::Change, failure::missing_key_error};
+use anyhow::Result;
 use once_cell::sync::Lazy;
+use rand::Rng;
 use regex::{Captures, Regex};
 use std::borrow::Cow;
 
-pub fn modify_temp<'a>(mission: &'a str, weather: &Weather, dry_run: bool) -> Result<Cow<'a, str>> {
+pub fn modify_temp<'a>(
+    mission: &'a str,
+    weather: &Weather,
+    rng: &mut impl Rng,
+    changes: &mut Vec<Change>,
+    dry_run: bool,
+) -> Result<Cow<'a, str>> {
     static REGEX: Lazy<Regex> =
         Lazy::new(|| Regex::new(r#"(\["temperature"\]) = [\d\.]+,"#).unwrap());
 
-    if let Some(temperature) = weather.random_temp() {
+    if let Some(temperature) = weather.random_temp(rng) {
         if !dry_run && !REGEX.is_match(mission) {
-            return Err(anyhow!("Could not find temperature key in mission file"));
+            return Err(missing_key_error("temperature key"));
         }
-        println!("   Temperature:           {:.2} °C", temperature);
+        changes.push(Change::new("Temperature", format!("{:.2} °C", temperature)));
         Ok(REGEX.replace(mission, |cap: &Captures| {
             format!("{} = {:.2},", &cap[1], temperature)
         }))
@@ -21,14 +28,20 @@ pub fn modify_temp<'a>(mission: &'a str, weather: &Weather, dry_run: bool) -> Re
     }
 }
 
-pub fn modify_qnh<'a>(mission: &'a str, weather: &Weather, dry_run: bool) -> Result<Cow<'a, str>> {
+pub fn modify_qnh<'a>(
+    mission: &'a str,
+    weather: &Weather,
+    rng: &mut impl Rng,
+    changes: &mut Vec<Change>,
+    dry_run: bool,
+) -> Result<Cow<'a, str>> {
     static REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(\["qnh"\]) = [\d\.]+,"#).unwrap());
 
-    if let Some(qnh) = weather.random_qnh() {
+    if let Some(qnh) = weather.random_qnh(rng) {
         if !dry_run && !REGEX.is_match(mission) {
-            return Err(anyhow!("Could not find QNH key in mission file"));
+            return Err(missing_key_error("QNH key"));
         }
-        println!("   QNH:                   {:.2} mmHg", qnh);
+        changes.push(Change::new("QNH", format!("{:.2} mmHg", qnh)));
         Ok(REGEX.replace(mission, |cap: &Captures| {
             format!("{} = {:.2},", &cap[1], qnh)
         }))
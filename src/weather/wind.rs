@@ -1,6 +1,7 @@
-use crate::config::Weather;
-use anyhow::{anyhow, Result};
+use crate::{config::Weather, editor::Change, failure::missing_key_error};
+use anyhow::Result;
 use once_cell::sync::Lazy;
+use rand::Rng;
 use regex::{Captures, Regex};
 use std::borrow::Cow;
 
@@ -20,7 +21,9 @@ macro_rules! wind_regex {
 pub fn modify_ground_wind<'a>(
     mission: &'a str,
     weather: &Weather,
+    rng: &mut impl Rng,
     wind_ground_speed: &mut f64,
+    changes: &mut Vec<Change>,
     dry_run: bool,
 ) -> Result<Cow<'a, str>> {
     static SPEED_REGEX: Lazy<Regex> = wind_regex!("atGround", "speed");
@@ -28,13 +31,14 @@ pub fn modify_ground_wind<'a>(
 
     let mut mission = Cow::Borrowed(mission);
 
-    if let Some(wind_speed) = weather.random_wind_speed_ground() {
+    if let Some(wind_speed) = weather.random_wind_speed_ground(rng) {
         if !dry_run && !SPEED_REGEX.is_match(&mission) {
-            return Err(anyhow!(
-                "Could not find ground wind speed key in mission file"
-            ));
+            return Err(missing_key_error("ground wind speed key"));
         }
-        println!("   Ground wind speed:     {:.1} m/s", wind_speed);
+        changes.push(Change::new(
+            "Ground wind speed",
+            format!("{:.1} m/s", wind_speed),
+        ));
         let new_mission = SPEED_REGEX.replace(&mission, |cap: &Captures| {
             format!("{} = {:.1},", &cap[1], wind_speed)
         });
@@ -43,13 +47,14 @@ pub fn modify_ground_wind<'a>(
         mission = Cow::Owned(new_mission.into_owned());
     }
 
-    if let Some(wind_heading) = weather.random_wind_heading_ground() {
+    if let Some(wind_heading) = weather.random_wind_heading_ground(rng) {
         if !dry_run && !HEADING_REGEX.is_match(&mission) {
-            return Err(anyhow!(
-                "Could not find ground wind direction key in mission file"
-            ));
+            return Err(missing_key_error("ground wind direction key"));
         }
-        println!("   Ground wind heading:   {}°", wind_heading);
+        changes.push(Change::new(
+            "Ground wind heading",
+            format!("{}°", wind_heading),
+        ));
         let new_mission = HEADING_REGEX.replace(&mission, |cap: &Captures| {
             format!("{} = {},", &cap[1], wind_heading)
         });
@@ -63,8 +68,10 @@ pub fn modify_ground_wind<'a>(
 pub fn modify_2000m_wind<'a>(
     mission: &'a str,
     weather: &Weather,
+    rng: &mut impl Rng,
     ground_speed: f64,
     wind_2000m_speed: &mut f64,
+    changes: &mut Vec<Change>,
     dry_run: bool,
 ) -> Result<Cow<'a, str>> {
     static SPEED_REGEX: Lazy<Regex> = wind_regex!("at2000", "speed");
@@ -72,13 +79,14 @@ pub fn modify_2000m_wind<'a>(
 
     let mut mission = Cow::Borrowed(mission);
 
-    if let Some(wind_speed) = weather.random_wind_speed_2000m(ground_speed) {
+    if let Some(wind_speed) = weather.random_wind_speed_2000m(rng, ground_speed) {
         if !dry_run && !SPEED_REGEX.is_match(&mission) {
-            return Err(anyhow!(
-                "Could not find 2000m wind speed key in mission file"
-            ));
+            return Err(missing_key_error("2000m wind speed key"));
         }
-        println!("   2000m wind speed:      {:.1} m/s", wind_speed);
+        changes.push(Change::new(
+            "2000m wind speed",
+            format!("{:.1} m/s", wind_speed),
+        ));
         let new_mission = SPEED_REGEX.replace(&mission, |cap: &Captures| {
             format!("{} = {:.1},", &cap[1], wind_speed)
         });
@@ -87,13 +95,14 @@ pub fn modify_2000m_wind<'a>(
         mission = Cow::Owned(new_mission.into_owned());
     }
 
-    if let Some(wind_heading) = weather.random_wind_heading_2000m() {
+    if let Some(wind_heading) = weather.random_wind_heading_2000m(rng) {
         if !dry_run && !HEADING_REGEX.is_match(&mission) {
-            return Err(anyhow!(
-                "Could not find 2000m wind direction key in mission file"
-            ));
+            return Err(missing_key_error("2000m wind direction key"));
         }
-        println!("   2000m wind heading:    {}°", wind_heading);
+        changes.push(Change::new(
+            "2000m wind heading",
+            format!("{}°", wind_heading),
+        ));
         let new_mission = HEADING_REGEX.replace(&mission, |cap: &Captures| {
             format!("{} = {},", &cap[1], wind_heading)
         });
@@ -107,7 +116,9 @@ pub fn modify_2000m_wind<'a>(
 pub fn modify_8000m_wind<'a>(
     mission: &'a str,
     weather: &Weather,
+    rng: &mut impl Rng,
     wind_2000m_speed: f64,
+    changes: &mut Vec<Change>,
     dry_run: bool,
 ) -> Result<Cow<'a, str>> {
     static SPEED_REGEX: Lazy<Regex> = wind_regex!("at8000", "speed");
@@ -115,13 +126,14 @@ pub fn modify_8000m_wind<'a>(
 
     let mut mission = Cow::Borrowed(mission);
 
-    if let Some(wind_speed) = weather.random_wind_speed_8000m(wind_2000m_speed) {
+    if let Some(wind_speed) = weather.random_wind_speed_8000m(rng, wind_2000m_speed) {
         if !dry_run && !SPEED_REGEX.is_match(&mission) {
-            return Err(anyhow!(
-                "Could not find 8000m wind speed key in mission file"
-            ));
+            return Err(missing_key_error("8000m wind speed key"));
         }
-        println!("   8000m wind speed:      {:.1} m/s", wind_speed);
+        changes.push(Change::new(
+            "8000m wind speed",
+            format!("{:.1} m/s", wind_speed),
+        ));
         let new_mission = SPEED_REGEX.replace(&mission, |cap: &Captures| {
             format!("{} = {:.1},", &cap[1], wind_speed)
         });
@@ -129,13 +141,14 @@ pub fn modify_8000m_wind<'a>(
         mission = Cow::Owned(new_mission.into_owned());
     }
 
-    if let Some(wind_heading) = weather.random_wind_heading_8000m() {
+    if let Some(wind_heading) = weather.random_wind_heading_8000m(rng) {
         if !dry_run && !HEADING_REGEX.is_match(&mission) {
-            return Err(anyhow!(
-                "Could not find 8000m wind direction key in mission file"
-            ));
+            return Err(missing_key_error("8000m wind direction key"));
         }
-        println!("   8000m wind heading:    {}°", wind_heading);
+        changes.push(Change::new(
+            "8000m wind heading",
+            format!("{}°", wind_heading),
+        ));
         let new_mission = HEADING_REGEX.replace(&mission, |cap: &Captures| {
             format!("{} = {},", &cap[1], wind_heading)
         });